@@ -0,0 +1,18 @@
+// Fragment shader of the `material` example, used with the vertex stage of `instancing.wgsl`.
+
+#import fundamentals::instance_batch::batch_time
+
+@group(2) @binding(0) var<uniform> glow_color: vec4f;
+@group(2) @binding(1) var<uniform> frequency: f32;
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+};
+
+@fragment
+fn fs(vertex_output: VertexOutput) -> @location(0) vec4f {
+    // A wave running across the screen.
+    let phase = batch_time() * frequency - vertex_output.position.x * 0.02;
+    return mix(vertex_output.color, glow_color, 0.5 + 0.5 * sin(phase));
+}
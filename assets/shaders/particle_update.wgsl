@@ -0,0 +1,37 @@
+// Spins every particle and moves it along a circle around its offset.
+
+#import fundamentals::instance_simulation::{simulation, changing_data, changing_offset}
+
+fn rotate(v: vec2f, angle: f32) -> vec2f {
+    let c = cos(angle);
+    let s = sin(angle);
+    return vec2f(c * v.x - s * v.y, s * v.x + c * v.y);
+}
+
+@compute @workgroup_size(64)
+fn update(@builtin(global_invocation_id) global_id: vec3u) {
+    let instance = global_id.x;
+    if instance >= simulation.instance_count {
+        return;
+    }
+
+    // `AffineInstanceData` is an x axis, a y axis and a translation.
+    let c = changing_offset(instance);
+    let x_axis = vec2f(changing_data[c], changing_data[c + 1u]);
+    let y_axis = vec2f(changing_data[c + 2u], changing_data[c + 3u]);
+    let translation = vec2f(changing_data[c + 4u], changing_data[c + 5u]);
+
+    // Some particles turn faster than others, and in the other direction.
+    let speed = f32(instance % 7u) - 3.0;
+    let angle = speed * simulation.delta_time;
+    let new_x_axis = rotate(x_axis, angle);
+    let new_y_axis = rotate(y_axis, angle);
+    let new_translation = rotate(translation, angle * 0.5);
+
+    changing_data[c] = new_x_axis.x;
+    changing_data[c + 1u] = new_x_axis.y;
+    changing_data[c + 2u] = new_y_axis.x;
+    changing_data[c + 3u] = new_y_axis.y;
+    changing_data[c + 4u] = new_translation.x;
+    changing_data[c + 5u] = new_translation.y;
+}
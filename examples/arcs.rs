@@ -0,0 +1,56 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use fundamentals::{
+    ArcInstanceData, ChangingInstanceData, InstanceMaterialData, InstancingPlugin, Shape2d,
+};
+
+type Arcs = InstanceMaterialData<ArcInstanceData, ChangingInstanceData>;
+
+const COLUMNS: usize = 42;
+const ROWS: usize = 24;
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((DefaultPlugins, InstancingPlugin::arcs()))
+        .add_systems(Startup, setup)
+        .add_systems(Update, fill_rings)
+        .run()
+}
+
+fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    let (static_data, changing_data) = (0..ROWS)
+        .flat_map(|y| (0..COLUMNS).map(move |x| Vec2::new(x as f32, y as f32)))
+        .map(|cell| {
+            (
+                ArcInstanceData {
+                    color: LinearRgba::from(Color::hsl(cell.x * 8.5, 0.8, 0.6)).to_f32_array(),
+                    offset: (cell - Vec2::new(COLUMNS as f32, ROWS as f32) / 2.0) * 28.0,
+                    ..ArcInstanceData::progress(0.0, 0.6)
+                },
+                ChangingInstanceData {
+                    scale: Vec2::splat(12.0),
+                },
+            )
+        })
+        .unzip();
+
+    // A thousand rings with different fill amounts, in a single draw call.
+    commands.spawn((
+        Mesh2d(meshes.add(Shape2d::arc_template(64).mesh())),
+        Transform::default(),
+        Visibility::default(),
+        Arcs::new(static_data, changing_data),
+    ));
+
+    commands.spawn(Camera2d);
+}
+
+fn fill_rings(time: Res<Time>, mut batches: Query<&mut Arcs>) {
+    for mut batch in &mut batches {
+        for (i, arc) in batch.static_data_mut().iter_mut().enumerate() {
+            let fraction = (time.elapsed_secs() * 0.2 + i as f32 * 0.013).fract();
+            arc.end_angle = arc.start_angle - fraction * TAU;
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use fundamentals::{
+    ChangingInstanceData, InstanceMaterialData, InstancingPlugin, StaticInstanceData,
+    create_circle_shape,
+};
+use rand::{Rng, SeedableRng};
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((DefaultPlugins, InstancingPlugin::new()))
+        .add_systems(Startup, setup)
+        .run()
+}
+
+fn setup(
+    mut commands: Commands,
+    window: Single<&Window, With<PrimaryWindow>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let aspect = window.width() / window.height();
+
+    let mesh_handle =
+        meshes.add(create_circle_shape(0.5, 24, 0.25, 0.0, std::f32::consts::PI * 2.0).mesh());
+
+    let mut rand = rand_chacha::ChaCha8Rng::from_os_rng();
+    // Spawning 1 entity
+    commands.spawn((
+        Mesh2d(mesh_handle),
+        // Offsets are in [-1, 1], so stretch the batch over the whole window.
+        Transform::from_scale(Vec3::new(window.width() / 2.0, window.height() / 2.0, 1.0)),
+        Visibility::default(),
+        InstanceMaterialData::new(
+            (1..=100)
+                .flat_map(|x| (1..10).map(move |y| (x as f32 / 10.0, y as f32 / 10.)))
+                .map(|(x, y)| StaticInstanceData {
+                    offset: Vec2::new(
+                        rand.random_range(-1_f32..1.0),
+                        rand.random_range(-1_f32..1.0),
+                    ),
+                    color: LinearRgba::from(Color::hsla(x * 360., y, 0.5, 1.0)).to_f32_array(),
+                    ..default()
+                })
+                .collect(),
+            (0..900)
+                .map(|_| ChangingInstanceData {
+                    scale: Vec2::splat(rand.random_range(0.25..1_f32)) / aspect,
+                })
+                .collect(),
+        ),
+    ));
+
+    commands.spawn((
+        Camera2d,
+        // Transform::from_xyz(0.0, 0.0, 15.0).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+}
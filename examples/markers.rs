@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+use fundamentals::{
+    ChangingInstanceData, InstanceMaterialData, InstancingPlugin, Shape2d, StrokedInstanceData,
+};
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((DefaultPlugins, InstancingPlugin::strokes()))
+        .add_systems(Startup, setup)
+        .run()
+}
+
+fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    // Fill and stroke come from the same mesh, so each batch is a single draw call.
+    let markers = [
+        Shape2d::circle(1.0, 32),
+        Shape2d::star(1.0, 0.45, 5),
+        Shape2d::rounded_rectangle(Vec2::ONE, 0.3, 4),
+        Shape2d::ring(1.0, 0.6, 32),
+    ];
+
+    for (row, marker) in markers.into_iter().enumerate() {
+        let (static_data, changing_data) = (0..12)
+            .map(|column| {
+                (
+                    StrokedInstanceData {
+                        color: LinearRgba::from(Color::hsl(column as f32 * 30.0, 0.8, 0.6))
+                            .to_f32_array(),
+                        offset: Vec2::new(column as f32 * 60.0, 0.0),
+                        stroke_color: LinearRgba::from(Color::srgb(0.1, 0.1, 0.15)).to_f32_array(),
+                        // In mesh units, before the instance is scaled up.
+                        stroke_width: column as f32 * 0.02,
+                    },
+                    ChangingInstanceData {
+                        scale: Vec2::splat(20.0),
+                    },
+                )
+            })
+            .unzip();
+
+        commands.spawn((
+            Mesh2d(meshes.add(marker.with_stroke().mesh())),
+            Transform::from_xyz(-330.0, 150.0 - row as f32 * 100.0, 0.0),
+            Visibility::default(),
+            InstanceMaterialData::new(static_data, changing_data),
+        ));
+    }
+
+    commands.spawn((
+        Camera2d,
+        Camera {
+            clear_color: ClearColorConfig::Custom(Color::WHITE),
+            ..default()
+        },
+    ));
+}
@@ -0,0 +1,74 @@
+use bevy::{
+    asset::RenderAssetUsages, prelude::*, render::render_resource::AsBindGroup, shader::ShaderRef,
+};
+use fundamentals::{
+    ATTRIBUTE_CUSTOM_POSITION, ChangingInstanceData, InstanceMaterialData, InstancedMaterial2d,
+    InstancedMaterial2dPlugin, InstancedMeshMaterial2d, InstancingPlugin, StaticInstanceData,
+    create_circle_vertices,
+};
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((
+            DefaultPlugins,
+            InstancingPlugin::new(),
+            InstancedMaterial2dPlugin::<GlowMaterial>::default(),
+        ))
+        .add_systems(Startup, setup)
+        .run()
+}
+
+/// Blends the instance colors with a glow sweeping across the screen.
+#[derive(Asset, TypePath, AsBindGroup, Clone)]
+struct GlowMaterial {
+    #[uniform(0)]
+    color: LinearRgba,
+    #[uniform(1)]
+    frequency: f32,
+}
+
+impl InstancedMaterial2d for GlowMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/instanced_material.wgsl".into()
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<GlowMaterial>>,
+) {
+    let mut mesh = Mesh::new(
+        bevy::mesh::PrimitiveTopology::TriangleList,
+        RenderAssetUsages::all(),
+    );
+    mesh.insert_attribute(
+        ATTRIBUTE_CUSTOM_POSITION,
+        create_circle_vertices(20.0, 24, 0.0, 0.0, std::f32::consts::PI * 2.0),
+    );
+
+    let (columns, rows) = (30, 16);
+    commands.spawn((
+        Mesh2d(meshes.add(mesh)),
+        InstancedMeshMaterial2d(materials.add(GlowMaterial {
+            color: LinearRgba::WHITE,
+            frequency: 3.0,
+        })),
+        InstanceMaterialData::new(
+            (0..columns * rows)
+                .map(|i| StaticInstanceData {
+                    offset: Vec2::new(
+                        ((i % columns) as f32 - columns as f32 / 2.0) * 45.0,
+                        ((i / columns) as f32 - rows as f32 / 2.0) * 45.0,
+                    ),
+                    color: LinearRgba::from(Color::hsl(i as f32 / 2.0 % 360.0, 0.7, 0.4))
+                        .to_f32_array(),
+                    ..default()
+                })
+                .collect(),
+            vec![ChangingInstanceData { scale: Vec2::ONE }; columns * rows],
+        ),
+    ));
+
+    commands.spawn(Camera2d);
+}
@@ -0,0 +1,67 @@
+use bevy::{
+    asset::RenderAssetUsages, camera::visibility::NoFrustumCulling, prelude::*,
+    window::PrimaryWindow,
+};
+use fundamentals::{
+    ATTRIBUTE_CUSTOM_POSITION, AffineInstanceData, InstanceMaterialData, InstancingPlugin,
+    PackedAffine2, StaticInstanceData, create_circle_vertices,
+};
+use rand::{Rng, SeedableRng};
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((
+            DefaultPlugins,
+            InstancingPlugin::affine().with_simulation_shader("shaders/particle_update.wgsl"),
+        ))
+        .add_systems(Startup, setup)
+        .run()
+}
+
+fn setup(
+    mut commands: Commands,
+    window: Single<&Window, With<PrimaryWindow>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let mut mesh = Mesh::new(
+        bevy::mesh::PrimitiveTopology::TriangleList,
+        RenderAssetUsages::all(),
+    );
+    mesh.insert_attribute(
+        ATTRIBUTE_CUSTOM_POSITION,
+        create_circle_vertices(4.0, 3, 0.0, 0.0, std::f32::consts::PI * 2.0),
+    );
+
+    let mut rand = rand_chacha::ChaCha8Rng::from_os_rng();
+    let half_size = window.size() / 2.0;
+    let count = 10_000;
+    commands.spawn((
+        Mesh2d(meshes.add(mesh)),
+        Visibility::default(),
+        // The batch bounds are computed from the initial instances, which the simulation moves.
+        NoFrustumCulling,
+        InstanceMaterialData::new(
+            (0..count)
+                .map(|_| StaticInstanceData {
+                    offset: Vec2::new(
+                        rand.random_range(-half_size.x..half_size.x),
+                        rand.random_range(-half_size.y..half_size.y),
+                    ),
+                    color: LinearRgba::from(Color::hsl(rand.random_range(0.0..360.0), 0.8, 0.6))
+                        .to_f32_array(),
+                    ..default()
+                })
+                .collect(),
+            (0..count)
+                .map(|_| AffineInstanceData {
+                    transform: PackedAffine2 {
+                        translation: Vec2::new(rand.random_range(10.0..40.0), 0.0),
+                        ..PackedAffine2::from_scale(Vec2::splat(rand.random_range(0.5..2.0)))
+                    },
+                })
+                .collect(),
+        ),
+    ));
+
+    commands.spawn(Camera2d);
+}
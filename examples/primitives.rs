@@ -0,0 +1,76 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+use fundamentals::{
+    ATTRIBUTE_CUSTOM_POSITION, ChangingInstanceData, InstanceMaterialData, InstancingPlugin,
+    StaticInstanceData,
+};
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((DefaultPlugins, InstancingPlugin::new()))
+        .add_systems(Startup, setup)
+        .run()
+}
+
+fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    // Bevy's primitives have 3D positions, normals and UVs.
+    let shapes = [
+        meshes.add(Circle::new(20.0)),
+        meshes.add(RegularPolygon::new(20.0, 6)),
+        meshes.add(Annulus::new(12.0, 20.0)),
+        meshes.add(Capsule2d::new(10.0, 20.0)),
+        meshes.add(star_outline(20.0, 8.0, 5)),
+    ];
+
+    for (i, mesh) in shapes.into_iter().enumerate() {
+        let hue = i as f32 * 72.0;
+        let (static_data, changing_data) = (0..10)
+            .flat_map(|x| (0..3).map(move |y| Vec2::new(x as f32, y as f32) * 50.0))
+            .map(|position| {
+                (
+                    StaticInstanceData {
+                        color: LinearRgba::from(Color::hsl(hue + position.x / 5.0, 0.8, 0.6))
+                            .to_f32_array(),
+                        offset: position,
+                        ..default()
+                    },
+                    ChangingInstanceData { scale: Vec2::ONE },
+                )
+            })
+            .unzip();
+
+        commands.spawn((
+            Mesh2d(mesh),
+            Transform::from_xyz(-225.0, 250.0 - i as f32 * 125.0, 0.0),
+            Visibility::default(),
+            InstanceMaterialData::new(static_data, changing_data),
+        ));
+    }
+
+    commands.spawn(Camera2d);
+}
+
+/// Closed outline of a star, drawn as an indexed line strip.
+fn star_outline(outer_radius: f32, inner_radius: f32, points: u32) -> Mesh {
+    let positions: Vec<[f32; 2]> = (0..points * 2)
+        .map(|i| {
+            let radius = if i % 2 == 0 {
+                outer_radius
+            } else {
+                inner_radius
+            };
+            let angle =
+                std::f32::consts::FRAC_PI_2 + i as f32 * std::f32::consts::PI / points as f32;
+            (Vec2::from_angle(angle) * radius).to_array()
+        })
+        .collect();
+    // The first point is repeated to close the strip.
+    let indices = (0..points * 2).chain([0]).map(|i| i as u16).collect();
+
+    Mesh::new(PrimitiveTopology::LineStrip, RenderAssetUsages::all())
+        .with_inserted_attribute(ATTRIBUTE_CUSTOM_POSITION, positions)
+        .with_inserted_indices(Indices::U16(indices))
+}
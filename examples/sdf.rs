@@ -0,0 +1,53 @@
+use bevy::prelude::*;
+use fundamentals::{
+    ChangingInstanceData, InstanceMaterialData, InstancingPlugin, SdfInstanceData, Shape2d,
+};
+
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((DefaultPlugins, InstancingPlugin::sdf()))
+        .add_systems(Startup, setup)
+        .add_systems(Update, zoom)
+        .run()
+}
+
+fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    let outline = Color::srgb(0.1, 0.1, 0.15);
+    let shapes = (0..8).flat_map(|i| {
+        let x = i as f32 * 60.0 - 210.0;
+        let color = Color::hsl(i as f32 * 45.0, 0.7, 0.6);
+        [
+            SdfInstanceData::circle(20.0),
+            SdfInstanceData::ring(20.0, 6.0),
+            SdfInstanceData::rounded_box(Vec2::new(22.0, 14.0), i as f32 * 2.0),
+            SdfInstanceData::segment(Vec2::new(-20.0, -15.0), Vec2::new(20.0, 15.0), 6.0),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(move |(row, shape)| {
+            let offset = shape.offset + Vec2::new(x, 90.0 - row as f32 * 60.0);
+            shape
+                .with_offset(offset)
+                .with_color(color)
+                .with_outline(i as f32 * 0.5, outline)
+        })
+    });
+    let static_data: Vec<_> = shapes.collect();
+    let changing_data = vec![ChangingInstanceData { scale: Vec2::ONE }; static_data.len()];
+
+    commands.spawn((
+        Mesh2d(meshes.add(Shape2d::rectangle(Vec2::ONE).mesh())),
+        Transform::default(),
+        Visibility::default(),
+        InstanceMaterialData::new(static_data, changing_data),
+    ));
+
+    commands.spawn(Camera2d);
+}
+
+/// Edges stay smooth at any scale.
+fn zoom(time: Res<Time>, mut projection: Single<&mut Projection, With<Camera2d>>) {
+    if let Projection::Orthographic(orthographic) = projection.as_mut() {
+        orthographic.scale = 0.3 + 0.7 * (time.elapsed_secs() * 0.5).cos().abs();
+    }
+}
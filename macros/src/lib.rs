@@ -0,0 +1,82 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, parse_macro_input};
+
+/// Implements `InstanceAttributes` by concatenating the attributes of every field, in declaration
+/// order.
+///
+/// Fields marked with `#[instance(skip)]` (e.g. padding) are not exposed to the shader.
+#[proc_macro_derive(InstanceAttributes, attributes(instance))]
+pub fn derive_instance_attributes(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return syn::Error::new_spanned(
+                    name,
+                    "InstanceAttributes can only be derived for structs with named fields",
+                )
+                .to_compile_error()
+                .into();
+            }
+        },
+        _ => {
+            return syn::Error::new_spanned(
+                name,
+                "InstanceAttributes can only be derived for structs",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+
+    let mut field_attributes = Vec::new();
+    for field in fields {
+        let mut skip = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("instance"))
+        {
+            if let Err(err) = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `skip`"))
+                }
+            }) {
+                return err.to_compile_error().into();
+            }
+        }
+        if skip {
+            continue;
+        }
+
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        field_attributes.push(quote! {
+            for attribute in <#ty as ::fundamentals::InstanceAttributes>::attributes() {
+                attributes.push(::fundamentals::InstanceAttribute {
+                    format: attribute.format,
+                    offset: attribute.offset + ::core::mem::offset_of!(Self, #ident) as u64,
+                });
+            }
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::fundamentals::InstanceAttributes for #name #ty_generics #where_clause {
+            fn attributes() -> ::std::vec::Vec<::fundamentals::InstanceAttribute> {
+                let mut attributes = ::std::vec::Vec::new();
+                #(#field_attributes)*
+                attributes
+            }
+        }
+    }
+    .into()
+}
@@ -0,0 +1,18 @@
+// Lets the derive macros refer to `::fundamentals` from inside this crate as well.
+extern crate self as fundamentals;
+
+pub mod shapes;
+pub mod vertex_buffer;
+
+pub use fundamentals_macros::InstanceAttributes;
+
+pub use shapes::{Shape2d, create_circle_shape, create_circle_vertices};
+
+pub use vertex_buffer::{
+    ATTRIBUTE_CUSTOM_POSITION, ATTRIBUTE_STROKE_OFFSET, AffineInstanceData, ArcInstanceData,
+    ChangingInstanceData, InstanceAttribute, InstanceAttributes, InstanceBlendMode,
+    InstanceCulling, InstanceMaterialData, InstancePayload, InstanceTexture, InstanceUniformData,
+    InstancedMaterial2d, InstancedMaterial2dPlugin, InstancedMeshMaterial2d, InstancingMode,
+    InstancingPlugin, PackedAffine2, SdfInstanceData, StaticInstanceData, StrokedInstanceData,
+    Transform2d,
+};
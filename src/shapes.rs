@@ -0,0 +1,717 @@
+//! 2D shapes as indexed meshes with [`ATTRIBUTE_CUSTOM_POSITION`].
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    platform::collections::HashMap,
+    prelude::*,
+};
+
+use crate::{ATTRIBUTE_CUSTOM_POSITION, ATTRIBUTE_STROKE_OFFSET};
+
+/// Vertices and counter-clockwise triangles of a 2D shape centered on the origin.
+///
+/// Turn it into a mesh with [`Shape2d::mesh`] or [`Shape2d::mesh_with_uvs`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Shape2d {
+    pub positions: Vec<[f32; 2]>,
+    /// Three per triangle.
+    pub indices: Vec<u32>,
+    /// One per position once [stroked](Shape2d::with_stroke), empty before.
+    pub stroke_offsets: Vec<[f32; 3]>,
+}
+
+impl Shape2d {
+    /// Circle made of `segments` triangles around its center, at least 3.
+    pub fn circle(radius: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let rim = (0..segments).map(|i| polar(radius, i as f32 * TAU / segments as f32));
+        Self::fan(Vec2::ZERO, rim)
+    }
+
+    /// Ring between `inner_radius` and `outer_radius`, sharing its vertices around the seam.
+    ///
+    /// A zero `inner_radius` gives a [`Shape2d::circle`]. There are at least 3 segments.
+    pub fn ring(outer_radius: f32, inner_radius: f32, segments: u32) -> Self {
+        if inner_radius <= 0.0 {
+            return Self::circle(outer_radius, segments);
+        }
+        let segments = segments.max(3);
+        let mut shape = Self::band(outer_radius, inner_radius, 0.0, TAU, segments);
+        // Stitch the last segment to the first one instead of repeating its vertices.
+        shape.positions.truncate(shape.positions.len() - 2);
+        let seam = shape.positions.len() as u32;
+        for index in &mut shape.indices {
+            *index %= seam;
+        }
+        shape
+    }
+
+    /// Part of a ring going counter-clockwise from `start_angle` to `end_angle`, in radians from
+    /// the positive x axis.
+    ///
+    /// A zero `inner_radius` gives a circular sector, fanning out from the center. There is at
+    /// least one segment.
+    pub fn arc(
+        outer_radius: f32,
+        inner_radius: f32,
+        start_angle: f32,
+        end_angle: f32,
+        segments: u32,
+    ) -> Self {
+        let (start_angle, end_angle) = if end_angle < start_angle {
+            (end_angle, start_angle)
+        } else {
+            (start_angle, end_angle)
+        };
+        Self::band(outer_radius, inner_radius, start_angle, end_angle, segments)
+    }
+
+    /// Rectangle of `half_size`, as two triangles.
+    pub fn rectangle(half_size: Vec2) -> Self {
+        let Vec2 { x, y } = half_size;
+        Self {
+            positions: vec![[x, y], [-x, y], [-x, -y], [x, -y]],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..default()
+        }
+    }
+
+    /// Rectangle of `half_size` whose corners are quarter circles of `corner_radius`, each made
+    /// of `corner_segments` segments.
+    ///
+    /// A single segment cuts the corners off in a straight line.
+    pub fn rounded_rectangle(half_size: Vec2, corner_radius: f32, corner_segments: u32) -> Self {
+        let corner_radius = corner_radius.clamp(0.0, half_size.min_element());
+        // Sharp corners are a single vertex.
+        let corner_segments = if corner_radius > 0.0 {
+            corner_segments.max(1)
+        } else {
+            0
+        };
+        let inner = half_size - corner_radius;
+        let corners = [
+            inner,
+            Vec2::new(-inner.x, inner.y),
+            -inner,
+            Vec2::new(inner.x, -inner.y),
+        ];
+        let rim = corners
+            .into_iter()
+            .enumerate()
+            .flat_map(|(corner, center)| {
+                let start_angle = corner as f32 * FRAC_PI_2;
+                (0..=corner_segments).map(move |i| {
+                    // Sharp corners only use the first angle.
+                    let step = FRAC_PI_2 / corner_segments.max(1) as f32;
+                    center + polar(corner_radius, start_angle + i as f32 * step)
+                })
+            });
+        Self::fan(Vec2::ZERO, rim)
+    }
+
+    /// Polygon with `sides` corners on a circle of `radius`, the first one pointing up like
+    /// Bevy's `RegularPolygon`. There are at least 3 sides.
+    pub fn regular_polygon(radius: f32, sides: u32) -> Self {
+        let sides = sides.max(3);
+        let rim = (0..sides).map(|i| polar(radius, FRAC_PI_2 + i as f32 * TAU / sides as f32));
+        Self::fan(Vec2::ZERO, rim)
+    }
+
+    /// Star with `points` tips on a circle of `outer_radius` and its notches on a circle of
+    /// `inner_radius`, the first tip pointing up. There are at least 2 tips.
+    pub fn star(outer_radius: f32, inner_radius: f32, points: u32) -> Self {
+        let points = points.max(2);
+        let rim = (0..points * 2).map(|i| {
+            let radius = if i % 2 == 0 {
+                outer_radius
+            } else {
+                inner_radius
+            };
+            polar(radius, FRAC_PI_2 + i as f32 * PI / points as f32)
+        });
+        Self::fan(Vec2::ZERO, rim)
+    }
+
+    /// Vertical capsule like Bevy's `Capsule2d`: a rectangle of `2 * half_length` by
+    /// `2 * radius` capped by half circles of `segments` segments, at least 1.
+    pub fn capsule(radius: f32, half_length: f32, segments: u32) -> Self {
+        let segments = segments.max(1);
+        let cap = |center: f32, start_angle: f32| {
+            (0..=segments).map(move |i| {
+                Vec2::new(0.0, center)
+                    + polar(radius, start_angle + i as f32 * PI / segments as f32)
+            })
+        };
+        let rim = cap(half_length, 0.0).chain(cap(-half_length, PI));
+        Self::fan(Vec2::ZERO, rim)
+    }
+
+    /// Line of `thickness` through `points`, with mitered joints.
+    ///
+    /// Joints turning by more than about 150 degrees are cut short rather than growing into long
+    /// spikes.
+    pub fn line_strip(points: &[Vec2], thickness: f32) -> Self {
+        let half_thickness = thickness / 2.0;
+        let normal = |from: Vec2, to: Vec2| (to - from).normalize_or_zero().perp();
+
+        let mut shape = Self::default();
+        for (i, &point) in points.iter().enumerate() {
+            let before = i.checked_sub(1).map(|before| normal(points[before], point));
+            let after = points.get(i + 1).map(|&next| normal(point, next));
+            let offset = match (before, after) {
+                (Some(before), Some(after)) => {
+                    let miter = (before + after).normalize_or(before);
+                    miter * half_thickness / miter.dot(before).max(0.25)
+                }
+                (Some(normal), None) | (None, Some(normal)) => normal * half_thickness,
+                (None, None) => break,
+            };
+            // The right side comes first.
+            shape.positions.push((point - offset).to_array());
+            shape.positions.push((point + offset).to_array());
+        }
+        shape.indices = strip_indices((shape.positions.len() as u32 / 2).saturating_sub(1));
+        shape
+    }
+
+    /// Mesh for [`InstancingPlugin::arcs`](crate::InstancingPlugin::arcs), bent into each
+    /// instance's arc by the vertex shader.
+    ///
+    /// Positions aren't the shape itself: x goes from 0 at the start of the arc to 1 at its end,
+    /// in `segments` steps, and y from 0 on the outer edge to 1 on the inner one. There is at
+    /// least one step.
+    pub fn arc_template(segments: u32) -> Self {
+        let segments = segments.max(1);
+        let positions = (0..=segments)
+            .flat_map(|i| {
+                let x = i as f32 / segments as f32;
+                [[x, 0.0], [x, 1.0]]
+            })
+            .collect();
+        let indices = strip_indices(segments);
+        Self {
+            positions,
+            indices,
+            ..default()
+        }
+    }
+
+    /// Triangles between a center vertex and consecutive `rim` vertices, counter-clockwise when
+    /// the rim is.
+    fn fan(center: Vec2, rim: impl IntoIterator<Item = Vec2>) -> Self {
+        let positions: Vec<[f32; 2]> = std::iter::once(center)
+            .chain(rim)
+            .map(|position| position.to_array())
+            .collect();
+        let rim_len = positions.len() as u32 - 1;
+        let indices = (0..rim_len)
+            .flat_map(|i| [0, i + 1, (i + 1) % rim_len + 1])
+            .collect();
+        Self {
+            positions,
+            indices,
+            ..default()
+        }
+    }
+
+    /// Quads between an outer and an inner arc, their vertices interleaved outer first.
+    ///
+    /// Without an inner arc, the quads would be degenerate triangles at the center, so the outer
+    /// arc is fanned out from the center instead.
+    fn band(
+        outer_radius: f32,
+        inner_radius: f32,
+        start_angle: f32,
+        end_angle: f32,
+        segments: u32,
+    ) -> Self {
+        let segments = segments.max(1);
+        let angle = |i: u32| start_angle + i as f32 * (end_angle - start_angle) / segments as f32;
+
+        if inner_radius <= 0.0 {
+            let positions = std::iter::once(Vec2::ZERO)
+                .chain((0..=segments).map(|i| polar(outer_radius, angle(i))))
+                .map(|position| position.to_array())
+                .collect();
+            let indices = (0..segments).flat_map(|i| [0, i + 1, i + 2]).collect();
+            return Self {
+                positions,
+                indices,
+                ..default()
+            };
+        }
+
+        let positions = (0..=segments)
+            .flat_map(|i| [polar(outer_radius, angle(i)), polar(inner_radius, angle(i))])
+            .map(|position| position.to_array())
+            .collect();
+        let indices = strip_indices(segments);
+        Self {
+            positions,
+            indices,
+            ..default()
+        }
+    }
+
+    /// Adds a stroke around every edge of the shape, drawn by
+    /// [`InstancingPlugin::strokes`](crate::InstancingPlugin::strokes) with the
+    /// [`stroke_color`](crate::StrokedInstanceData::stroke_color) and
+    /// [`stroke_width`](crate::StrokedInstanceData::stroke_width) of each instance.
+    ///
+    /// The stroke is a band of triangles without area along the outline, whose outer vertices
+    /// the shader moves out by the stroke width, see [`ATTRIBUTE_STROKE_OFFSET`]. Other shaders
+    /// can ignore it. Corners are mitered, up to 4 times the stroke width.
+    pub fn with_stroke(mut self) -> Self {
+        // Outline edges belong to a single triangle, which is on their left.
+        let mut edge_triangles: HashMap<(u32, u32), u32> = HashMap::default();
+        let edges = |triangle: &[u32]| {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+            [(a, b), (b, c), (c, a)]
+        };
+        for (a, b) in self.indices.chunks_exact(3).flat_map(edges) {
+            *edge_triangles.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+        let outline: Vec<(u32, u32)> = self
+            .indices
+            .chunks_exact(3)
+            .flat_map(edges)
+            .filter(|&(a, b)| edge_triangles[&(a.min(b), a.max(b))] == 1)
+            .collect();
+
+        let position = |index: u32| Vec2::from(self.positions[index as usize]);
+        let mut normals: HashMap<u32, Vec<Vec2>> = HashMap::default();
+        for &(a, b) in &outline {
+            let normal = -(position(b) - position(a)).normalize_or_zero().perp();
+            normals.entry(a).or_default().push(normal);
+            normals.entry(b).or_default().push(normal);
+        }
+
+        let fill_len = self.positions.len();
+        self.stroke_offsets = vec![[0.0; 3]; fill_len];
+        // Inner and outer stroke vertex of each outline vertex, in outline order.
+        let mut stroke_vertices: HashMap<u32, u32> = HashMap::default();
+        for &(a, b) in &outline {
+            let [inner_a, inner_b] = [a, b].map(|vertex| {
+                *stroke_vertices.entry(vertex).or_insert_with(|| {
+                    let normals = &normals[&vertex];
+                    let miter = normals.iter().sum::<Vec2>().normalize_or_zero();
+                    let scale = normals
+                        .iter()
+                        .map(|normal| miter.dot(*normal))
+                        .fold(1.0_f32, f32::min)
+                        .max(0.25);
+                    let offset = miter / scale;
+                    let inner = self.positions.len() as u32;
+                    self.positions.extend([self.positions[vertex as usize]; 2]);
+                    self.stroke_offsets
+                        .extend([[0.0, 0.0, 1.0], [offset.x, offset.y, 1.0]]);
+                    inner
+                })
+            });
+            let (outer_a, outer_b) = (inner_a + 1, inner_b + 1);
+            self.indices
+                .extend([outer_a, outer_b, inner_a, inner_a, outer_b, inner_b]);
+        }
+        self
+    }
+
+    /// Triangle list mesh of the shape.
+    pub fn mesh(&self) -> Mesh {
+        let indices = match u16::try_from(self.positions.len()) {
+            Ok(_) => Indices::U16(self.indices.iter().map(|&i| i as u16).collect()),
+            Err(_) => Indices::U32(self.indices.clone()),
+        };
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(ATTRIBUTE_CUSTOM_POSITION, self.positions.clone())
+        .with_inserted_indices(indices);
+        if !self.stroke_offsets.is_empty() {
+            mesh.insert_attribute(ATTRIBUTE_STROKE_OFFSET, self.stroke_offsets.clone());
+        }
+        mesh
+    }
+
+    /// Like [`Shape2d::mesh`], with `Mesh::ATTRIBUTE_UV_0` stretching a texture over the
+    /// bounding box of the shape.
+    pub fn mesh_with_uvs(&self) -> Mesh {
+        self.mesh()
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs())
+    }
+
+    fn uvs(&self) -> Vec<[f32; 2]> {
+        let (min, max) = self.positions.iter().fold(
+            (Vec2::INFINITY, Vec2::NEG_INFINITY),
+            |(min, max), &position| (min.min(position.into()), max.max(position.into())),
+        );
+        let size = (max - min).max(Vec2::splat(f32::EPSILON));
+        self.positions
+            .iter()
+            .map(|&[x, y]| [(x - min.x) / size.x, (max.y - y) / size.y])
+            .collect()
+    }
+}
+
+impl From<Shape2d> for Mesh {
+    fn from(shape: Shape2d) -> Self {
+        shape.mesh()
+    }
+}
+
+/// Two triangles for each of the `quads` between consecutive pairs of vertices, counter-clockwise
+/// when the first vertex of each pair is on the right.
+fn strip_indices(quads: u32) -> Vec<u32> {
+    (0..quads)
+        .flat_map(|i| {
+            let (right, left) = (i * 2, i * 2 + 1);
+            [right, right + 2, left, left, right + 2, left + 2]
+        })
+        .collect()
+}
+
+fn polar(radius: f32, angle: f32) -> Vec2 {
+    Vec2::from_angle(angle) * radius
+}
+
+/// Indexed equivalent of [`create_circle_vertices`], sharing the vertices of adjacent triangles.
+///
+/// Full turns give a [`Shape2d::ring`] and other angles a [`Shape2d::arc`], both fanning out from
+/// the center when `inner_radius` is 0.
+pub fn create_circle_shape(
+    radius: f32,
+    num_subdivisions: u32,
+    inner_radius: f32,
+    start_angle: f32,
+    end_angle: f32,
+) -> Shape2d {
+    if (end_angle - start_angle).abs() >= TAU {
+        Shape2d::ring(radius, inner_radius, num_subdivisions)
+    } else {
+        Shape2d::arc(
+            radius,
+            inner_radius,
+            start_angle,
+            end_angle,
+            num_subdivisions,
+        )
+    }
+}
+
+/// Default values:
+///
+/// radius: 1.0,
+///
+/// num_subdivisions: 24,
+///
+/// inner_radius: 0.0,
+///
+/// start_angle: 0.0,
+///
+/// end_angle: std::f32::consts::PI * 2,
+pub fn create_circle_vertices(
+    radius: f32,
+    num_subdivisions: u32,
+    inner_radius: f32,
+    start_angle: f32,
+    end_angle: f32,
+) -> Vec<[f32; 2]> {
+    let num_vertices = num_subdivisions as usize * 3 * 2;
+    let mut vertex_data = Vec::with_capacity(num_vertices);
+
+    let mut add_vertex = |x, y| {
+        // Bevy requires data to be given as array
+        vertex_data.push([x, y]);
+    };
+
+    for i in 0..num_subdivisions {
+        let angle_1 =
+            start_angle + (i as f32 + 0.0) * (end_angle - start_angle) / num_subdivisions as f32;
+        let angle_2 =
+            start_angle + (i as f32 + 1.0) * (end_angle - start_angle) / num_subdivisions as f32;
+
+        let c1 = angle_1.cos();
+        let s1 = angle_1.sin();
+        let c2 = angle_2.cos();
+        let s2 = angle_2.sin();
+
+        // First triangle
+        add_vertex(c1 * radius, s1 * radius);
+        add_vertex(c2 * radius, s2 * radius);
+        add_vertex(c1 * inner_radius, s1 * inner_radius);
+
+        // Second triangle
+        add_vertex(c1 * inner_radius, s1 * inner_radius);
+        add_vertex(c2 * radius, s2 * radius);
+        add_vertex(c2 * inner_radius, s2 * inner_radius);
+    }
+
+    vertex_data
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::mesh::VertexAttributeValues;
+
+    use super::*;
+
+    /// Signed area of every triangle, positive when counter-clockwise.
+    fn triangle_areas(shape: &Shape2d) -> Vec<f32> {
+        shape
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| Vec2::from(shape.positions[triangle[i] as usize]));
+                (b - a).perp_dot(c - a) / 2.0
+            })
+            .collect()
+    }
+
+    fn assert_shape(shape: &Shape2d, vertices: usize, triangles: usize) {
+        assert_eq!(shape.positions.len(), vertices);
+        assert_eq!(shape.indices.len(), triangles * 3);
+        assert!(
+            shape
+                .indices
+                .iter()
+                .all(|&index| (index as usize) < vertices)
+        );
+        for area in triangle_areas(shape) {
+            assert!(area > 0.0, "triangle is not counter-clockwise: {area}");
+        }
+    }
+
+    #[test]
+    fn circle() {
+        assert_shape(&Shape2d::circle(1.0, 24), 25, 24);
+    }
+
+    #[test]
+    fn ring_shares_the_seam() {
+        assert_shape(&Shape2d::ring(1.0, 0.5, 24), 48, 48);
+    }
+
+    #[test]
+    fn arc() {
+        assert_shape(&Shape2d::arc(1.0, 0.5, 0.0, PI, 12), 26, 24);
+        // Reversed angles cover the same arc.
+        assert_eq!(
+            Shape2d::arc(1.0, 0.5, PI, 0.0, 12),
+            Shape2d::arc(1.0, 0.5, 0.0, PI, 12)
+        );
+    }
+
+    #[test]
+    fn zero_inner_radius_fans_out_from_the_center() {
+        assert_eq!(Shape2d::ring(1.0, 0.0, 24), Shape2d::circle(1.0, 24));
+        assert_shape(&Shape2d::arc(1.0, 0.0, 0.0, PI, 12), 14, 12);
+    }
+
+    #[test]
+    fn circle_shapes_share_vertices() {
+        let full_turn = (0.0, TAU);
+        for (inner_radius, (start_angle, end_angle), vertices, triangles) in [
+            (0.25, full_turn, 48, 48),
+            (0.0, full_turn, 25, 24),
+            (0.25, (0.0, PI), 50, 48),
+            (0.0, (0.0, PI), 26, 24),
+        ] {
+            let shape = create_circle_shape(0.5, 24, inner_radius, start_angle, end_angle);
+            assert_shape(&shape, vertices, triangles);
+            // Every vertex is used by at least one triangle.
+            assert!((0..vertices as u32).all(|vertex| shape.indices.contains(&vertex)));
+        }
+        // The triangle soup duplicates every vertex.
+        assert_eq!(create_circle_vertices(0.5, 24, 0.25, 0.0, TAU).len(), 144);
+    }
+
+    #[test]
+    fn circle_shape_area_matches_the_ring_area() {
+        let (radius, segments) = (2.0_f32, 512);
+        for (inner_radius, (start_angle, end_angle)) in [
+            (0.5, (0.0, TAU)),
+            (0.0, (0.0, TAU)),
+            (1.5, (0.25, 2.0)),
+            (0.0, (-1.0, 1.0)),
+        ] {
+            let shape = create_circle_shape(radius, segments, inner_radius, start_angle, end_angle);
+            let area: f32 = triangle_areas(&shape).into_iter().sum();
+            let expected =
+                (end_angle - start_angle) / 2.0 * (radius.powi(2) - inner_radius.powi(2));
+            assert!(
+                (area - expected).abs() < expected * 1e-3,
+                "area {area} instead of {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn too_few_segments_are_clamped() {
+        let clamped = [
+            (Shape2d::circle(1.0, 0), Shape2d::circle(1.0, 3)),
+            (Shape2d::ring(1.0, 0.5, 0), Shape2d::ring(1.0, 0.5, 3)),
+            (
+                Shape2d::arc(1.0, 0.5, 0.0, FRAC_PI_2, 0),
+                Shape2d::arc(1.0, 0.5, 0.0, FRAC_PI_2, 1),
+            ),
+            (
+                Shape2d::rounded_rectangle(Vec2::ONE, 0.5, 0),
+                Shape2d::rounded_rectangle(Vec2::ONE, 0.5, 1),
+            ),
+            (
+                Shape2d::regular_polygon(1.0, 0),
+                Shape2d::regular_polygon(1.0, 3),
+            ),
+            (Shape2d::star(1.0, 0.5, 0), Shape2d::star(1.0, 0.5, 2)),
+            (Shape2d::capsule(1.0, 1.0, 0), Shape2d::capsule(1.0, 1.0, 1)),
+            (Shape2d::arc_template(0), Shape2d::arc_template(1)),
+        ];
+        for (shape, expected) in clamped {
+            assert_eq!(shape, expected);
+            assert!(shape.positions.iter().flatten().all(|x| x.is_finite()));
+            assert!(triangle_areas(&shape).into_iter().all(|area| area > 0.0));
+        }
+        // A single segment per corner cuts them off straight.
+        assert_shape(&Shape2d::rounded_rectangle(Vec2::ONE, 0.5, 0), 9, 8);
+    }
+
+    #[test]
+    fn rectangle() {
+        assert_shape(&Shape2d::rectangle(Vec2::new(2.0, 1.0)), 4, 2);
+    }
+
+    #[test]
+    fn rounded_rectangle() {
+        assert_shape(
+            &Shape2d::rounded_rectangle(Vec2::new(2.0, 1.0), 0.5, 4),
+            21,
+            20,
+        );
+        // Without a radius, corners are a single vertex.
+        assert_shape(
+            &Shape2d::rounded_rectangle(Vec2::new(2.0, 1.0), 0.0, 4),
+            5,
+            4,
+        );
+    }
+
+    #[test]
+    fn regular_polygon() {
+        let hexagon = Shape2d::regular_polygon(1.0, 6);
+        assert_shape(&hexagon, 7, 6);
+        let top = Vec2::from(hexagon.positions[1]);
+        assert!(top.abs_diff_eq(Vec2::Y, 1e-6));
+    }
+
+    #[test]
+    fn arc_template() {
+        let template = Shape2d::arc_template(32);
+        assert_shape(&template, 66, 64);
+        assert_eq!(template.positions[64..], [[1.0, 0.0], [1.0, 1.0]]);
+    }
+
+    #[test]
+    fn star() {
+        assert_shape(&Shape2d::star(1.0, 0.4, 5), 11, 10);
+    }
+
+    #[test]
+    fn capsule() {
+        assert_shape(&Shape2d::capsule(0.5, 1.0, 8), 19, 18);
+    }
+
+    #[test]
+    fn line_strip() {
+        let points = [
+            Vec2::ZERO,
+            Vec2::X,
+            Vec2::new(2.0, 0.5),
+            Vec2::new(3.0, 0.0),
+        ];
+        let line = Shape2d::line_strip(&points, 0.2);
+        assert_shape(&line, 8, 6);
+
+        // Each pair of vertices straddles its point.
+        let [right, left] = [line.positions[0], line.positions[1]].map(Vec2::from);
+        assert!(right.abs_diff_eq(Vec2::new(0.0, -0.1), 1e-6));
+        assert!(left.abs_diff_eq(Vec2::new(0.0, 0.1), 1e-6));
+
+        assert_shape(&Shape2d::line_strip(&[Vec2::ZERO], 0.2), 0, 0);
+    }
+
+    /// The shape with its stroke moved out by `width`, like the built-in shader does.
+    fn extruded(shape: &Shape2d, width: f32) -> Shape2d {
+        let positions = shape
+            .positions
+            .iter()
+            .zip(&shape.stroke_offsets)
+            .map(|(&[x, y], &[dx, dy, _])| [x + dx * width, y + dy * width])
+            .collect();
+        Shape2d {
+            positions,
+            indices: shape.indices.clone(),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn stroke_surrounds_the_outline() {
+        let rectangle = Shape2d::rectangle(Vec2::new(2.0, 1.0)).with_stroke();
+        assert_eq!(rectangle.stroke_offsets.len(), 12);
+        // Only the outer stroke vertices move.
+        let moving = rectangle
+            .stroke_offsets
+            .iter()
+            .filter(|offset| offset[..2] != [0.0, 0.0])
+            .count();
+        assert_eq!(moving, 4);
+
+        let stroked = extruded(&rectangle, 0.5);
+        assert_shape(&stroked, 12, 10);
+        let area: f32 = triangle_areas(&stroked).into_iter().sum();
+        assert!((area - 5.0 * 3.0).abs() < 1e-4, "area {area}");
+        // Mitered corners.
+        assert!(stroked.positions.contains(&[2.5, 1.5]));
+
+        // Rings have an outline on both sides.
+        let (radius, inner_radius, width) = (2.0_f32, 1.0_f32, 0.25);
+        let ring = extruded(
+            &Shape2d::ring(radius, inner_radius, 512).with_stroke(),
+            width,
+        );
+        assert_shape(&ring, 512 * 6, 512 * 6);
+        let area: f32 = triangle_areas(&ring).into_iter().sum();
+        let expected = PI * ((radius + width).powi(2) - (inner_radius - width).powi(2));
+        assert!((area - expected).abs() < expected * 1e-3, "area {area}");
+    }
+
+    #[test]
+    fn stroke_without_width_is_invisible() {
+        let star = Shape2d::star(1.0, 0.4, 5);
+        let stroked = star.clone().with_stroke();
+        assert_eq!(stroked.positions[..star.positions.len()], star.positions);
+        let area: f32 = triangle_areas(&stroked).into_iter().sum();
+        let fill: f32 = triangle_areas(&star).into_iter().sum();
+        assert!((area - fill).abs() < 1e-5);
+
+        assert!(star.mesh().attribute(ATTRIBUTE_STROKE_OFFSET).is_none());
+        assert!(stroked.mesh().attribute(ATTRIBUTE_STROKE_OFFSET).is_some());
+    }
+
+    #[test]
+    fn mesh_attributes() {
+        let mesh = Shape2d::rounded_rectangle(Vec2::new(2.0, 1.0), 0.0, 0).mesh_with_uvs();
+        assert_eq!(mesh.primitive_topology(), PrimitiveTopology::TriangleList);
+        assert_eq!(mesh.indices().map(Indices::len), Some(12));
+        let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+        else {
+            panic!("missing UVs");
+        };
+        // The center, then the top right corner.
+        assert_eq!(uvs[..2], [[0.5, 0.5], [1.0, 0.0]]);
+    }
+}
@@ -0,0 +1,93 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use bevy::{math::Affine2, prelude::*};
+
+use super::ChangingInstanceData;
+
+/// Static payload of [`InstancingPlugin::arcs`](super::InstancingPlugin::arcs), an arc of a
+/// ring of radius 1 per instance.
+///
+/// The batch's mesh is a [`Shape2d::arc_template`](crate::Shape2d::arc_template), bent into each
+/// instance's arc by the vertex shader, so arcs of any angle and thickness share one draw call.
+#[derive(
+    Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, fundamentals_macros::InstanceAttributes,
+)]
+#[repr(C)]
+pub struct ArcInstanceData {
+    pub color: [f32; 4],
+    pub offset: Vec2,
+    /// Radians counter-clockwise from the positive x axis. The arc goes clockwise when
+    /// `end_angle` is smaller than `start_angle`.
+    pub start_angle: f32,
+    pub end_angle: f32,
+    /// Inner radius as a fraction of the outer one, 0 for pie slices.
+    pub inner_radius: f32,
+}
+
+impl Default for ArcInstanceData {
+    /// A white disc.
+    fn default() -> Self {
+        Self {
+            color: [1.0; 4],
+            offset: Vec2::ZERO,
+            start_angle: 0.0,
+            end_angle: TAU,
+            inner_radius: 0.0,
+        }
+    }
+}
+
+impl ArcInstanceData {
+    /// Progress ring filled clockwise from the top by `fraction`, between 0 and 1.
+    pub fn progress(fraction: f32, inner_radius: f32) -> Self {
+        Self {
+            start_angle: FRAC_PI_2,
+            end_angle: FRAC_PI_2 - fraction.clamp(0.0, 1.0) * TAU,
+            inner_radius,
+            ..default()
+        }
+    }
+}
+
+/// Maps the `[0, 1]` square of the arc template onto the square around the whole ring, which
+/// bounds the arc whatever its angles.
+pub(super) fn arc_instance_transform(
+    static_data: &ArcInstanceData,
+    changing_data: &ChangingInstanceData,
+) -> Affine2 {
+    Affine2::from_scale_angle_translation(
+        2.0 * changing_data.scale,
+        0.0,
+        static_data.offset - changing_data.scale,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arcs_are_bounded_by_their_ring() {
+        let static_data = ArcInstanceData {
+            offset: Vec2::new(5.0, 0.0),
+            ..ArcInstanceData::progress(0.25, 0.5)
+        };
+        let changing_data = ChangingInstanceData {
+            scale: Vec2::splat(3.0),
+        };
+        let transform = arc_instance_transform(&static_data, &changing_data);
+        assert_eq!(transform.transform_point2(Vec2::ZERO), Vec2::new(2.0, -3.0));
+        assert_eq!(transform.transform_point2(Vec2::ONE), Vec2::new(8.0, 3.0));
+    }
+
+    #[test]
+    fn progress_goes_clockwise_from_the_top() {
+        let quarter = ArcInstanceData::progress(0.25, 0.5);
+        assert_eq!(quarter.start_angle, FRAC_PI_2);
+        assert_eq!(quarter.end_angle, 0.0);
+        assert_eq!(
+            ArcInstanceData::progress(2.0, 0.5).end_angle,
+            FRAC_PI_2 - TAU
+        );
+    }
+}
@@ -0,0 +1,105 @@
+// Built-in shader of `InstancingPlugin::arcs`, bending an arc template mesh into the arc of
+// each `ArcInstanceData` instance.
+
+#import bevy_render::view::View
+#import fundamentals::instance_batch::instance_batch
+
+@group(0) @binding(0) var<uniform> view: View;
+
+#ifdef STORAGE_INSTANCING
+@group(3) @binding(0) var<storage, read> static_data: array<f32>;
+@group(3) @binding(1) var<storage, read> changing_data: array<f32>;
+#endif
+
+struct Vertex {
+    // Along the arc in x and from its outer to its inner edge in y, both from 0 to 1.
+    @location(0) position: vec2f,
+#ifndef STORAGE_INSTANCING
+    @location(4) color: vec4f,
+    @location(5) offset: vec2f,
+    @location(6) start_angle: f32,
+    @location(7) end_angle: f32,
+    @location(8) inner_radius: f32,
+    @location(9) scale: vec2f,
+#endif
+};
+
+struct Instance {
+    color: vec4f,
+    offset: vec2f,
+    start_angle: f32,
+    end_angle: f32,
+    inner_radius: f32,
+    scale: vec2f,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+};
+
+#ifdef STORAGE_INSTANCING
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    let s = instance_index * #{STATIC_INSTANCE_STRIDE}u;
+    let c = instance_index * #{CHANGING_INSTANCE_STRIDE}u;
+
+    var instance: Instance;
+    instance.color = vec4f(
+        static_data[s], static_data[s + 1u], static_data[s + 2u], static_data[s + 3u]
+    );
+    instance.offset = vec2f(static_data[s + 4u], static_data[s + 5u]);
+    instance.start_angle = static_data[s + 6u];
+    instance.end_angle = static_data[s + 7u];
+    instance.inner_radius = static_data[s + 8u];
+    instance.scale = vec2f(changing_data[c], changing_data[c + 1u]);
+    return instance;
+}
+#else
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    return Instance(
+        vertex.color,
+        vertex.offset,
+        vertex.start_angle,
+        vertex.end_angle,
+        vertex.inner_radius,
+        vertex.scale,
+    );
+}
+#endif
+
+@vertex
+fn vs(
+    vertex: Vertex,
+    @builtin(instance_index) instance_index: u32
+) -> VertexOutput {
+    let instance = load_instance(vertex, instance_index);
+    let batch = instance_batch(instance_index);
+
+    let angle = mix(instance.start_angle, instance.end_angle, vertex.position.x);
+    let radius = mix(1.0, instance.inner_radius, vertex.position.y);
+    let arc_position = radius * vec2f(cos(angle), sin(angle));
+
+    var vertex_output: VertexOutput;
+    let instance_position = arc_position * instance.scale;
+    let local_position = vec4f(instance_position + instance.offset, 0.0, 1.0);
+    let world_position = batch.world_from_local * local_position;
+    vertex_output.position = view.clip_from_world * world_position;
+    vertex_output.color = instance.color * batch.tint;
+    return vertex_output;
+}
+
+@fragment
+fn fs(vertex_output: VertexOutput) -> @location(0) vec4f {
+    let color = vertex_output.color;
+#ifdef ALPHA_MASK
+    if color.a < 0.5 {
+        discard;
+    }
+#endif
+#ifdef BLEND_MULTIPLY
+    // Transparent parts leave the target unchanged.
+    return vec4f(mix(vec3f(1.0), color.rgb, color.a), color.a);
+#else
+    return color;
+#endif
+}
@@ -0,0 +1,236 @@
+use bevy::{
+    math::{IVec2, IVec3, IVec4, UVec2, UVec3, UVec4, Vec2, Vec3, Vec4},
+    mesh::{VertexBufferLayout, VertexFormat},
+    render::render_resource::{VertexAttribute, VertexStepMode},
+};
+
+/// A single attribute of an instance struct, at `offset` bytes from the start of the struct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceAttribute {
+    pub format: VertexFormat,
+    pub offset: u64,
+}
+
+/// Describes how an instance struct is laid out as per-instance vertex attributes.
+///
+/// Usually derived with `#[derive(InstanceAttributes)]`, which concatenates the attributes of
+/// every field so nested instance structs (like [`PackedAffine2`](super::PackedAffine2)) are
+/// flattened.
+pub trait InstanceAttributes: bytemuck::Pod {
+    fn attributes() -> Vec<InstanceAttribute>;
+
+    /// Instance-rate vertex buffer layout with shader locations starting at `first_location`.
+    fn vertex_buffer_layout(first_location: u32) -> VertexBufferLayout {
+        VertexBufferLayout {
+            step_mode: VertexStepMode::Instance,
+            array_stride: size_of::<Self>() as u64,
+            attributes: Self::attributes()
+                .into_iter()
+                .zip(first_location..)
+                .map(|(attribute, shader_location)| VertexAttribute {
+                    format: attribute.format,
+                    offset: attribute.offset,
+                    shader_location,
+                })
+                .collect(),
+        }
+    }
+
+    /// Checks that the attributes are aligned, don't overlap and stay within the struct.
+    fn validate_layout() -> Result<(), String> {
+        let stride = size_of::<Self>() as u64;
+        let mut attributes = Self::attributes();
+        if attributes.is_empty() {
+            return Err(format!(
+                "`{}` has no instance attributes",
+                std::any::type_name::<Self>()
+            ));
+        }
+
+        attributes.sort_by_key(|attribute| attribute.offset);
+        let mut end = 0;
+        for attribute in attributes {
+            if attribute.offset % 4 != 0 {
+                return Err(format!(
+                    "`{}` has an attribute at offset {} which is not 4-byte aligned",
+                    std::any::type_name::<Self>(),
+                    attribute.offset
+                ));
+            }
+            if attribute.offset < end {
+                return Err(format!(
+                    "`{}` has overlapping attributes at offset {}",
+                    std::any::type_name::<Self>(),
+                    attribute.offset
+                ));
+            }
+            end = attribute.offset + attribute.format.size();
+            if end > stride {
+                return Err(format!(
+                    "`{}` has an attribute ending at byte {} but is only {} bytes long",
+                    std::any::type_name::<Self>(),
+                    end,
+                    stride
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bounds required of the static and changing payloads of an
+/// [`InstanceMaterialData`](super::InstanceMaterialData).
+pub trait InstancePayload: InstanceAttributes + Send + Sync {}
+
+impl<T: InstanceAttributes + Send + Sync> InstancePayload for T {}
+
+macro_rules! impl_instance_attributes {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(
+            impl InstanceAttributes for $ty {
+                fn attributes() -> Vec<InstanceAttribute> {
+                    vec![InstanceAttribute {
+                        format: VertexFormat::$format,
+                        offset: 0,
+                    }]
+                }
+            }
+        )*
+    };
+}
+
+impl_instance_attributes!(
+    f32 => Float32,
+    [f32; 2] => Float32x2,
+    [f32; 3] => Float32x3,
+    [f32; 4] => Float32x4,
+    Vec2 => Float32x2,
+    Vec3 => Float32x3,
+    Vec4 => Float32x4,
+    u32 => Uint32,
+    [u32; 2] => Uint32x2,
+    [u32; 3] => Uint32x3,
+    [u32; 4] => Uint32x4,
+    UVec2 => Uint32x2,
+    UVec3 => Uint32x3,
+    UVec4 => Uint32x4,
+    i32 => Sint32,
+    [i32; 2] => Sint32x2,
+    [i32; 3] => Sint32x3,
+    [i32; 4] => Sint32x4,
+    IVec2 => Sint32x2,
+    IVec3 => Sint32x3,
+    IVec4 => Sint32x4,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AffineInstanceData, ChangingInstanceData, StaticInstanceData};
+
+    #[test]
+    fn builtin_instance_layouts() {
+        let static_layout = StaticInstanceData::vertex_buffer_layout(2);
+        assert_eq!(static_layout.array_stride, 10 * 4);
+        assert_eq!(
+            static_layout.attributes,
+            vec![
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 2,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x2,
+                    offset: 4 * 4,
+                    shader_location: 3,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 6 * 4,
+                    shader_location: 4,
+                },
+            ]
+        );
+
+        let changing_layout = ChangingInstanceData::vertex_buffer_layout(5);
+        assert_eq!(changing_layout.array_stride, 2 * 4);
+        assert_eq!(
+            changing_layout.attributes,
+            vec![VertexAttribute {
+                format: VertexFormat::Float32x2,
+                offset: 0,
+                shader_location: 5,
+            }]
+        );
+
+        let affine_layout = AffineInstanceData::vertex_buffer_layout(5);
+        assert_eq!(affine_layout.array_stride, 6 * 4);
+        assert_eq!(
+            affine_layout
+                .attributes
+                .iter()
+                .map(|attribute| (attribute.shader_location, attribute.offset))
+                .collect::<Vec<_>>(),
+            vec![(5, 0), (6, 2 * 4), (7, 4 * 4)]
+        );
+
+        assert_eq!(StaticInstanceData::validate_layout(), Ok(()));
+        assert_eq!(ChangingInstanceData::validate_layout(), Ok(()));
+        assert_eq!(AffineInstanceData::validate_layout(), Ok(()));
+    }
+
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, crate::InstanceAttributes)]
+    #[repr(C)]
+    struct Padded {
+        size: f32,
+        #[instance(skip)]
+        _padding: f32,
+        velocity: Vec2,
+    }
+
+    #[test]
+    fn skipped_fields_are_not_attributes() {
+        assert_eq!(
+            Padded::attributes(),
+            vec![
+                InstanceAttribute {
+                    format: VertexFormat::Float32,
+                    offset: 0,
+                },
+                InstanceAttribute {
+                    format: VertexFormat::Float32x2,
+                    offset: 8,
+                },
+            ]
+        );
+        assert_eq!(Padded::validate_layout(), Ok(()));
+    }
+
+    #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+    #[repr(C)]
+    struct Overlapping {
+        a: Vec2,
+        b: Vec2,
+    }
+
+    impl InstanceAttributes for Overlapping {
+        fn attributes() -> Vec<InstanceAttribute> {
+            vec![
+                InstanceAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 0,
+                },
+                InstanceAttribute {
+                    format: VertexFormat::Float32x2,
+                    offset: 8,
+                },
+            ]
+        }
+    }
+
+    #[test]
+    fn overlapping_attributes_are_rejected() {
+        assert!(Overlapping::validate_layout().is_err());
+    }
+}
@@ -0,0 +1,656 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    camera::{
+        primitives::{Aabb, Frustum},
+        visibility::NoFrustumCulling,
+    },
+    math::{Affine2, Affine3A},
+    mesh::VertexAttributeValues,
+    prelude::*,
+    render::{
+        Extract,
+        sync_world::{MainEntity, MainEntityHashMap},
+    },
+};
+
+use super::{
+    ATTRIBUTE_CUSTOM_POSITION, ATTRIBUTE_STROKE_OFFSET, AffineInstanceData, ChangingInstanceData,
+    InstanceMaterialData, InstancePayload, InstanceUniformData, StaticInstanceData,
+};
+
+/// Culls the instances of a batch individually, so only those in view are drawn.
+///
+/// Requires the plugin to know where instances are, see
+/// [`InstancingPlugin::with_instance_transform`](super::InstancingPlugin::with_instance_transform).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Component)]
+pub enum InstanceCulling {
+    /// The visible instances are gathered on the CPU and uploaded every frame. Worth it for
+    /// batches spread over a large world.
+    #[default]
+    Cpu,
+    /// A compute pass gathers the visible instances into a second set of buffers, which are
+    /// drawn indirectly. Scales to much larger batches, but doesn't keep the
+    /// [layer](InstanceMaterialData::with_layers) order.
+    ///
+    /// Needs compute shaders and, for custom payloads, a culling shader (see
+    /// [`InstancingPlugin::with_culling_shader`](super::InstancingPlugin::with_culling_shader)).
+    /// Batches are drawn unculled otherwise.
+    Gpu,
+}
+
+/// Places an instance within its batch, given its static and changing data.
+#[derive(Resource)]
+pub(super) struct InstanceTransform<S, C> {
+    pub(super) transform: fn(&S, &C) -> Affine2,
+    /// Width of the instance's [stroke](crate::Shape2d::with_stroke), in mesh units.
+    pub(super) stroke_width: Option<fn(&S, &C) -> f32>,
+}
+
+impl<S, C> Clone for InstanceTransform<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            transform: self.transform,
+            stroke_width: self.stroke_width,
+        }
+    }
+}
+
+impl<S, C> InstanceTransform<S, C> {
+    fn stroke_width(&self, static_data: &S, changing_data: &C) -> f32 {
+        self.stroke_width
+            .map_or(0.0, |stroke_width| stroke_width(static_data, changing_data))
+    }
+
+    /// Local bounds of an instance within its batch, stroke included.
+    fn instance_bounds(&self, static_data: &S, changing_data: &C, mesh_bounds: MeshBounds) -> Rect {
+        let stroke_width = self.stroke_width(static_data, changing_data);
+        transform_rect(
+            (self.transform)(static_data, changing_data),
+            mesh_bounds.with_stroke(stroke_width),
+        )
+    }
+}
+
+/// How the built-in `instancing.wgsl` places an instance.
+pub(super) fn builtin_instance_transform(
+    static_data: &StaticInstanceData,
+    changing_data: &ChangingInstanceData,
+) -> Affine2 {
+    Affine2::from_translation(static_data.offset) * Affine2::from_scale(changing_data.scale)
+}
+
+/// How the built-in `instancing.wgsl` places an instance with `AFFINE_INSTANCES`.
+pub(super) fn affine_instance_transform(
+    static_data: &StaticInstanceData,
+    changing_data: &AffineInstanceData,
+) -> Affine2 {
+    let mut transform = Affine2::from(changing_data.transform);
+    transform.translation += static_data.offset;
+    transform
+}
+
+/// Local bounds of a mesh and of the [stroke offsets](ATTRIBUTE_STROKE_OFFSET) of its vertices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MeshBounds {
+    positions: Rect,
+    /// Empty at the origin for meshes without a stroke.
+    stroke_offsets: Rect,
+}
+
+impl MeshBounds {
+    /// Bounds of the mesh once its stroke is `stroke_width` wide.
+    fn with_stroke(self, stroke_width: f32) -> Rect {
+        let stroke_width = stroke_width.max(0.0);
+        Rect {
+            min: self.positions.min + self.stroke_offsets.min * stroke_width,
+            max: self.positions.max + self.stroke_offsets.max * stroke_width,
+        }
+    }
+}
+
+/// Local bounds of the batch's mesh, along with the widest stroke of its instances.
+#[derive(Component, Clone, Copy, PartialEq)]
+pub(super) struct InstanceMeshBounds {
+    mesh: MeshBounds,
+    max_stroke_width: f32,
+}
+
+/// Mesh bounds of the batches using [`InstanceCulling`], rebuilt every extraction.
+#[derive(Resource)]
+pub(super) struct RenderInstanceCulling<S, C> {
+    mesh_bounds: MainEntityHashMap<(InstanceMeshBounds, InstanceCulling)>,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S, C> Default for RenderInstanceCulling<S, C> {
+    fn default() -> Self {
+        Self {
+            mesh_bounds: default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Bounds of the mesh positions in [`ATTRIBUTE_CUSTOM_POSITION`] or `Mesh::ATTRIBUTE_POSITION`,
+/// and of its stroke offsets.
+fn mesh_bounds(mesh: &Mesh) -> Option<MeshBounds> {
+    let positions: Vec<Vec2> = match mesh.attribute(ATTRIBUTE_CUSTOM_POSITION) {
+        Some(VertexAttributeValues::Float32x2(positions)) => {
+            positions.iter().copied().map(Vec2::from).collect()
+        }
+        _ => match mesh.attribute(Mesh::ATTRIBUTE_POSITION)? {
+            VertexAttributeValues::Float32x3(positions) => {
+                positions.iter().map(|&[x, y, _]| Vec2::new(x, y)).collect()
+            }
+            _ => return None,
+        },
+    };
+    let stroke_offsets = match mesh.attribute(ATTRIBUTE_STROKE_OFFSET) {
+        Some(VertexAttributeValues::Float32x3(offsets)) => {
+            bounds_of(offsets.iter().map(|&[x, y, _]| Vec2::new(x, y)))
+        }
+        _ => None,
+    };
+    Some(MeshBounds {
+        positions: bounds_of(positions)?,
+        // Vertices without a stroke offset stay in place.
+        stroke_offsets: stroke_offsets.unwrap_or_default().union_point(Vec2::ZERO),
+    })
+}
+
+fn bounds_of(points: impl IntoIterator<Item = Vec2>) -> Option<Rect> {
+    points.into_iter().fold(None, |bounds, point| {
+        Some(
+            bounds.map_or(Rect::from_corners(point, point), |bounds: Rect| {
+                bounds.union_point(point)
+            }),
+        )
+    })
+}
+
+/// Bounds of `rect` once transformed by `transform`.
+fn transform_rect(transform: Affine2, rect: Rect) -> Rect {
+    let corners = [
+        rect.min,
+        Vec2::new(rect.max.x, rect.min.y),
+        rect.max,
+        Vec2::new(rect.min.x, rect.max.y),
+    ];
+    bounds_of(corners.map(|corner| transform.transform_point2(corner))).unwrap()
+}
+
+/// Local bounds of every instance of the batch, or an empty box at the origin.
+fn batch_bounds<S, C>(
+    data: &InstanceMaterialData<S, C>,
+    mesh_bounds: MeshBounds,
+    instance_transform: &InstanceTransform<S, C>,
+) -> Rect
+where
+    S: InstancePayload,
+    C: InstancePayload,
+{
+    let instance_bounds = data
+        .static_data()
+        .iter()
+        .zip(data.changing_data())
+        .map(|(s, c)| instance_transform.instance_bounds(s, c, mesh_bounds));
+    instance_bounds
+        .reduce(|bounds, instance| bounds.union(instance))
+        .unwrap_or_default()
+}
+
+/// Widest stroke of the instances of the batch.
+fn max_stroke_width<S, C>(
+    data: &InstanceMaterialData<S, C>,
+    instance_transform: &InstanceTransform<S, C>,
+) -> f32
+where
+    S: InstancePayload,
+    C: InstancePayload,
+{
+    if instance_transform.stroke_width.is_none() {
+        return 0.0;
+    }
+    data.static_data()
+        .iter()
+        .zip(data.changing_data())
+        .map(|(s, c)| instance_transform.stroke_width(s, c))
+        .fold(0.0, f32::max)
+}
+
+fn aabb_from_rect(rect: Rect) -> Aabb {
+    Aabb::from_min_max(rect.min.extend(0.0), rect.max.extend(0.0))
+}
+
+/// Gives batches an [`Aabb`] covering all their instances, so Bevy can cull whole batches.
+///
+/// Bounds are updated when the batch, its [`InstanceUniformData`] or its `Mesh2d` changes, but
+/// not when the mesh asset itself is modified.
+pub(super) fn update_batch_aabbs<S: InstancePayload, C: InstancePayload>(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    instance_transform: Res<InstanceTransform<S, C>>,
+    batches: Query<
+        (
+            Entity,
+            &Mesh2d,
+            &InstanceMaterialData<S, C>,
+            &InstanceUniformData,
+            Option<&InstanceMeshBounds>,
+            Has<NoFrustumCulling>,
+        ),
+        Or<(
+            Changed<InstanceMaterialData<S, C>>,
+            Changed<InstanceUniformData>,
+            Changed<Mesh2d>,
+            Without<InstanceMeshBounds>,
+        )>,
+    >,
+) {
+    for (entity, mesh2d, data, uniform, previous_bounds, no_frustum_culling) in &batches {
+        let mesh_bounds = match meshes.get(&mesh2d.0).and_then(mesh_bounds) {
+            Some(mesh_bounds) => mesh_bounds,
+            // Try again once the mesh is loaded.
+            None => continue,
+        };
+
+        let mut entity = commands.entity(entity);
+        let bounds = InstanceMeshBounds {
+            mesh: mesh_bounds,
+            max_stroke_width: max_stroke_width(data, &instance_transform),
+        };
+        if previous_bounds.is_none_or(|previous| *previous != bounds) {
+            entity.insert(bounds);
+        }
+        if !no_frustum_culling {
+            let bounds = transform_rect(
+                uniform.affine(),
+                batch_bounds(data, mesh_bounds, &instance_transform),
+            );
+            entity.insert(aabb_from_rect(bounds));
+        }
+    }
+}
+
+pub(super) fn extract_instance_culling<S: InstancePayload, C: InstancePayload>(
+    query: Extract<
+        Query<(Entity, &InstanceMeshBounds, &InstanceCulling), With<InstanceMaterialData<S, C>>>,
+    >,
+    mut render_instance_culling: ResMut<RenderInstanceCulling<S, C>>,
+) {
+    render_instance_culling.mesh_bounds.clear();
+    for (entity, mesh_bounds, culling) in &query {
+        render_instance_culling
+            .mesh_bounds
+            .insert(entity.into(), (*mesh_bounds, *culling));
+    }
+}
+
+impl<S: InstancePayload, C: InstancePayload> RenderInstanceCulling<S, C> {
+    pub(super) fn is_culled_on_cpu(&self, main_entity: &MainEntity) -> bool {
+        matches!(
+            self.mesh_bounds.get(main_entity),
+            Some((_, InstanceCulling::Cpu))
+        )
+    }
+
+    /// Mesh bounds of the batch if it uses [`InstanceCulling::Gpu`], with room for the widest
+    /// stroke of its instances.
+    pub(super) fn gpu_mesh_bounds(&self, main_entity: &MainEntity) -> Option<Rect> {
+        match self.mesh_bounds.get(main_entity)? {
+            (bounds, InstanceCulling::Gpu) => {
+                Some(bounds.mesh.with_stroke(bounds.max_stroke_width))
+            }
+            (_, InstanceCulling::Cpu) => None,
+        }
+    }
+
+    /// Indices of the instances of the batch visible in at least one of the `frusta`, in the given
+    /// `order`. Returns `None` if the batch doesn't use [`InstanceCulling::Cpu`].
+    pub(super) fn visible_instances<'a>(
+        &self,
+        main_entity: &MainEntity,
+        data: &InstanceMaterialData<S, C>,
+        world_from_local: &Affine3A,
+        instance_transform: &InstanceTransform<S, C>,
+        frusta: impl Iterator<Item = &'a Frustum> + Clone,
+        order: Option<Vec<u32>>,
+    ) -> Option<Vec<u32>> {
+        let (bounds, InstanceCulling::Cpu) = *self.mesh_bounds.get(main_entity)? else {
+            return None;
+        };
+        let mut order = order.unwrap_or_else(|| (0..data.len() as u32).collect());
+        order.retain(|&index| {
+            let index = index as usize;
+            let aabb = aabb_from_rect(instance_transform.instance_bounds(
+                &data.static_data()[index],
+                &data.changing_data()[index],
+                bounds.mesh,
+            ));
+            frusta
+                .clone()
+                .any(|frustum| frustum.intersects_obb(&aabb, world_from_local, true, false))
+        });
+        Some(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::sync_world::MainEntity;
+
+    use super::*;
+    use crate::{PackedAffine2, Shape2d, StrokedInstanceData, Transform2d};
+
+    fn unstroked(positions: Rect) -> MeshBounds {
+        MeshBounds {
+            positions,
+            stroke_offsets: Rect::default(),
+        }
+    }
+
+    fn culling_bounds(positions: Rect) -> InstanceMeshBounds {
+        InstanceMeshBounds {
+            mesh: unstroked(positions),
+            max_stroke_width: 0.0,
+        }
+    }
+
+    fn builtin() -> InstanceTransform<StaticInstanceData, ChangingInstanceData> {
+        InstanceTransform {
+            transform: builtin_instance_transform,
+            stroke_width: None,
+        }
+    }
+
+    fn test_batch(offsets: &[Vec2], scale: f32) -> InstanceMaterialData {
+        InstanceMaterialData::new(
+            offsets
+                .iter()
+                .map(|&offset| StaticInstanceData {
+                    offset,
+                    ..default()
+                })
+                .collect(),
+            vec![
+                ChangingInstanceData {
+                    scale: Vec2::splat(scale),
+                };
+                offsets.len()
+            ],
+        )
+    }
+
+    #[test]
+    fn batch_bounds_cover_every_instance() {
+        let mesh_bounds = unstroked(Rect::new(-0.5, -0.5, 0.5, 0.5));
+        let data = test_batch(&[Vec2::new(-3.0, 1.0), Vec2::new(2.0, 4.0)], 2.0);
+
+        let bounds = batch_bounds(&data, mesh_bounds, &builtin());
+        assert_eq!(bounds, Rect::new(-4.0, 0.0, 3.0, 5.0));
+
+        let empty = test_batch(&[], 1.0);
+        assert_eq!(
+            batch_bounds(&empty, mesh_bounds, &builtin()),
+            Rect::default()
+        );
+    }
+
+    #[test]
+    fn rotated_instances_are_bounded() {
+        let transform = Affine2::from_angle(std::f32::consts::FRAC_PI_4);
+        let bounds = transform_rect(transform, Rect::new(-1.0, -1.0, 1.0, 1.0));
+        let extent = std::f32::consts::SQRT_2;
+        assert!(bounds.min.abs_diff_eq(Vec2::splat(-extent), 1e-5));
+        assert!(bounds.max.abs_diff_eq(Vec2::splat(extent), 1e-5));
+    }
+
+    #[test]
+    fn mesh_bounds_use_custom_positions() {
+        let mut mesh = Mesh::new(
+            bevy::mesh::PrimitiveTopology::TriangleList,
+            bevy::asset::RenderAssetUsages::all(),
+        );
+        assert_eq!(mesh_bounds(&mesh), None);
+
+        mesh.insert_attribute(
+            ATTRIBUTE_CUSTOM_POSITION,
+            vec![[0.0, -1.0], [2.0, 0.5], [-1.0, 0.0]],
+        );
+        assert_eq!(
+            mesh_bounds(&mesh),
+            Some(unstroked(Rect::new(-1.0, -1.0, 2.0, 0.5)))
+        );
+    }
+
+    #[test]
+    fn affine_batches_are_bounded_by_their_rotated_instances() {
+        let instance_transform = InstanceTransform {
+            transform: affine_instance_transform,
+            stroke_width: None,
+        };
+        let data = InstanceMaterialData::new(
+            vec![
+                StaticInstanceData {
+                    offset: Vec2::new(3.0, 0.0),
+                    ..default()
+                };
+                2
+            ],
+            vec![
+                AffineInstanceData {
+                    transform: Transform2d::from_rotation(Rot2::FRAC_PI_2)
+                        .with_scale(Vec2::new(2.0, 1.0))
+                        .into(),
+                },
+                AffineInstanceData {
+                    transform: PackedAffine2::from_translation(Vec2::new(0.0, 4.0)),
+                },
+            ],
+        );
+
+        let bounds = batch_bounds(
+            &data,
+            unstroked(Rect::new(-0.5, -0.5, 0.5, 0.5)),
+            &instance_transform,
+        );
+        assert!(bounds.min.abs_diff_eq(Vec2::new(2.5, -1.0), 1e-5));
+        assert!(bounds.max.abs_diff_eq(Vec2::new(3.5, 4.5), 1e-5));
+    }
+
+    #[test]
+    fn mesh_bounds_grow_with_the_stroke() {
+        let mesh = Shape2d::rectangle(Vec2::ONE).with_stroke().mesh();
+        let bounds = mesh_bounds(&mesh).unwrap().with_stroke(0.5);
+        // Mitered corners reach out diagonally.
+        assert!(bounds.min.abs_diff_eq(Vec2::splat(-1.5), 1e-5));
+        assert!(bounds.max.abs_diff_eq(Vec2::splat(1.5), 1e-5));
+    }
+
+    #[test]
+    fn strokes_are_part_of_the_instance_bounds() {
+        let main_entity = MainEntity::from(Entity::from_raw_u32(1).unwrap());
+        let mesh = MeshBounds {
+            positions: Rect::new(-0.5, -0.5, 0.5, 0.5),
+            stroke_offsets: Rect::new(-1.0, -1.0, 1.0, 1.0),
+        };
+        let instance_transform = InstanceTransform {
+            transform: crate::vertex_buffer::stroke::stroked_instance_transform,
+            stroke_width: Some(crate::vertex_buffer::stroke::stroke_width),
+        };
+        let stroked = |offset: Vec2, stroke_width: f32| StrokedInstanceData {
+            offset,
+            stroke_width,
+            ..default()
+        };
+        // Only the stroke of the second instance reaches into view.
+        let data = InstanceMaterialData::new(
+            vec![
+                stroked(Vec2::new(11.0, 0.0), 0.0),
+                stroked(Vec2::new(11.0, 0.0), 2.0),
+            ],
+            vec![ChangingInstanceData { scale: Vec2::ONE }; 2],
+        );
+
+        assert_eq!(
+            batch_bounds(&data, mesh, &instance_transform),
+            Rect::new(8.5, -2.5, 13.5, 2.5)
+        );
+        assert_eq!(max_stroke_width(&data, &instance_transform), 2.0);
+
+        let mut culling =
+            RenderInstanceCulling::<StrokedInstanceData, ChangingInstanceData>::default();
+        culling.mesh_bounds.insert(
+            main_entity,
+            (
+                InstanceMeshBounds {
+                    mesh,
+                    max_stroke_width: 2.0,
+                },
+                InstanceCulling::Cpu,
+            ),
+        );
+        let frustum = Frustum::from_clip_from_world(&Mat4::orthographic_rh(
+            -10.0, 10.0, -10.0, 10.0, -1000.0, 1000.0,
+        ));
+        assert_eq!(
+            culling.visible_instances(
+                &main_entity,
+                &data,
+                &Affine3A::IDENTITY,
+                &instance_transform,
+                [&frustum].into_iter(),
+                None,
+            ),
+            Some(vec![1])
+        );
+    }
+
+    #[test]
+    fn only_instances_in_view_are_kept() {
+        let main_entity = MainEntity::from(Entity::from_raw_u32(1).unwrap());
+        let mut culling =
+            RenderInstanceCulling::<StaticInstanceData, ChangingInstanceData>::default();
+        culling.mesh_bounds.insert(
+            main_entity,
+            (
+                culling_bounds(Rect::new(-0.5, -0.5, 0.5, 0.5)),
+                InstanceCulling::Cpu,
+            ),
+        );
+
+        // Sees [-10, 10] on both axes.
+        let frustum = Frustum::from_clip_from_world(&Mat4::orthographic_rh(
+            -10.0, 10.0, -10.0, 10.0, -1000.0, 1000.0,
+        ));
+        let data = test_batch(
+            &[
+                Vec2::new(0.0, 0.0),
+                Vec2::new(50.0, 0.0),
+                Vec2::new(10.4, -10.4),
+                Vec2::new(-5.0, 30.0),
+                Vec2::new(-9.0, 9.0),
+            ],
+            1.0,
+        );
+        let instance_transform = builtin();
+        let visible = |world_from_local: Affine3A, order: Option<Vec<u32>>| {
+            culling.visible_instances(
+                &main_entity,
+                &data,
+                &world_from_local,
+                &instance_transform,
+                [&frustum].into_iter(),
+                order,
+            )
+        };
+
+        assert_eq!(visible(Affine3A::IDENTITY, None), Some(vec![0, 2, 4]));
+        assert_eq!(
+            visible(Affine3A::IDENTITY, Some(vec![4, 3, 2, 1, 0])),
+            Some(vec![4, 2, 0])
+        );
+        // The batch transform applies to its instances.
+        assert_eq!(
+            visible(Affine3A::from_translation(Vec3::new(-45.0, 0.0, 0.0)), None),
+            Some(vec![1])
+        );
+
+        let other_entity = MainEntity::from(Entity::from_raw_u32(2).unwrap());
+        assert_eq!(
+            culling.visible_instances(
+                &other_entity,
+                &data,
+                &Affine3A::IDENTITY,
+                &instance_transform,
+                [&frustum].into_iter(),
+                None,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn gpu_culled_batches_are_left_to_the_compute_pass() {
+        let cpu_entity = MainEntity::from(Entity::from_raw_u32(1).unwrap());
+        let gpu_entity = MainEntity::from(Entity::from_raw_u32(2).unwrap());
+        let mesh_bounds = Rect::new(-0.5, -0.5, 0.5, 0.5);
+        let mut culling =
+            RenderInstanceCulling::<StaticInstanceData, ChangingInstanceData>::default();
+        culling.mesh_bounds.insert(
+            cpu_entity,
+            (culling_bounds(mesh_bounds), InstanceCulling::Cpu),
+        );
+        culling.mesh_bounds.insert(
+            gpu_entity,
+            (
+                InstanceMeshBounds {
+                    mesh: unstroked(mesh_bounds),
+                    max_stroke_width: 0.0,
+                },
+                InstanceCulling::Gpu,
+            ),
+        );
+
+        assert!(culling.is_culled_on_cpu(&cpu_entity));
+        assert!(!culling.is_culled_on_cpu(&gpu_entity));
+        assert_eq!(culling.gpu_mesh_bounds(&cpu_entity), None);
+        assert_eq!(culling.gpu_mesh_bounds(&gpu_entity), Some(mesh_bounds));
+
+        // The compute pass culls each instance with room for the widest stroke.
+        culling.mesh_bounds.insert(
+            gpu_entity,
+            (
+                InstanceMeshBounds {
+                    mesh: MeshBounds {
+                        positions: mesh_bounds,
+                        stroke_offsets: Rect::new(-1.0, -1.0, 1.0, 1.0),
+                    },
+                    max_stroke_width: 2.0,
+                },
+                InstanceCulling::Gpu,
+            ),
+        );
+        assert_eq!(
+            culling.gpu_mesh_bounds(&gpu_entity),
+            Some(Rect::new(-2.5, -2.5, 2.5, 2.5))
+        );
+
+        let data = test_batch(&[Vec2::new(100.0, 0.0)], 1.0);
+        let frustum = Frustum::from_clip_from_world(&Mat4::orthographic_rh(
+            -10.0, 10.0, -10.0, 10.0, -1000.0, 1000.0,
+        ));
+        assert_eq!(
+            culling.visible_instances(
+                &gpu_entity,
+                &data,
+                &Affine3A::IDENTITY,
+                &builtin(),
+                [&frustum].into_iter(),
+                None,
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,97 @@
+// Gathers the instances of a batch that are in view, for `InstanceCulling::Gpu`.
+//
+// Custom payloads need their own copy of this shader with `instance_transform` adapted to
+// their layout, see `InstancingPlugin::with_culling_shader`.
+
+struct CullingParams {
+    world_from_local: mat4x4f,
+    // min.xy, max.xy
+    mesh_bounds: vec4f,
+    instance_count: u32,
+    // Zero when there are too many views to cull against, in which case everything is kept.
+    view_count: u32,
+    // Left, right, bottom, top, near and far half spaces of each view.
+    half_spaces: array<vec4f, #{CULLING_HALF_SPACES}>,
+};
+
+@group(0) @binding(0) var<uniform> params: CullingParams;
+// Read as plain floats, like the storage buffer mode of `instancing.wgsl`.
+@group(0) @binding(1) var<storage, read> static_data: array<f32>;
+@group(0) @binding(2) var<storage, read> changing_data: array<f32>;
+@group(0) @binding(3) var<storage, read_write> culled_static_data: array<f32>;
+@group(0) @binding(4) var<storage, read_write> culled_changing_data: array<f32>;
+// `DrawIndirectArgs` or `DrawIndexedIndirectArgs`, both have the instance count at index 1.
+@group(0) @binding(5) var<storage, read_write> indirect_args: array<atomic<u32>>;
+
+// Places an instance within its batch, as columns `x_axis`, `y_axis` and `translation`.
+fn instance_transform(s: u32, c: u32) -> mat3x2f {
+    let offset = vec2f(static_data[s + 4u], static_data[s + 5u]);
+#ifdef AFFINE_INSTANCES
+    return mat3x2f(
+        vec2f(changing_data[c], changing_data[c + 1u]),
+        vec2f(changing_data[c + 2u], changing_data[c + 3u]),
+        vec2f(changing_data[c + 4u], changing_data[c + 5u]) + offset,
+    );
+#else
+    return mat3x2f(
+        vec2f(changing_data[c], 0.0),
+        vec2f(0.0, changing_data[c + 1u]),
+        offset,
+    );
+#endif
+}
+
+fn is_visible(transform: mat3x2f) -> bool {
+    if params.view_count == 0u {
+        return true;
+    }
+
+    let mesh_center = (params.mesh_bounds.xy + params.mesh_bounds.zw) * 0.5;
+    let mesh_half_size = (params.mesh_bounds.zw - params.mesh_bounds.xy) * 0.5;
+    let center = transform * vec3f(mesh_center, 1.0);
+    let half_size = abs(transform[0]) * mesh_half_size.x + abs(transform[1]) * mesh_half_size.y;
+
+    let world_center = (params.world_from_local * vec4f(center, 0.0, 1.0)).xyz;
+    let x_axis = params.world_from_local[0].xyz;
+    let y_axis = params.world_from_local[1].xyz;
+
+    for (var view = 0u; view < params.view_count; view += 1u) {
+        var inside = true;
+        // The far plane is ignored, like Bevy does for its own culling.
+        for (var i = 0u; i < 5u; i += 1u) {
+            let half_space = params.half_spaces[view * 6u + i];
+            let radius = abs(dot(half_space.xyz, x_axis)) * half_size.x
+                + abs(dot(half_space.xyz, y_axis)) * half_size.y;
+            if dot(half_space, vec4f(world_center, 1.0)) + radius <= 0.0 {
+                inside = false;
+                break;
+            }
+        }
+        if inside {
+            return true;
+        }
+    }
+    return false;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) global_id: vec3u) {
+    let instance = global_id.x;
+    if instance >= params.instance_count {
+        return;
+    }
+
+    let s = instance * #{STATIC_INSTANCE_STRIDE}u;
+    let c = instance * #{CHANGING_INSTANCE_STRIDE}u;
+    if !is_visible(instance_transform(s, c)) {
+        return;
+    }
+
+    let slot = atomicAdd(&indirect_args[1], 1u);
+    for (var i = 0u; i < #{STATIC_INSTANCE_STRIDE}u; i += 1u) {
+        culled_static_data[slot * #{STATIC_INSTANCE_STRIDE}u + i] = static_data[s + i];
+    }
+    for (var i = 0u; i < #{CHANGING_INSTANCE_STRIDE}u; i += 1u) {
+        culled_changing_data[slot * #{CHANGING_INSTANCE_STRIDE}u + i] = changing_data[c + i];
+    }
+}
@@ -0,0 +1,416 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    asset::AssetPath,
+    camera::primitives::Frustum,
+    math::Affine3A,
+    prelude::*,
+    render::{
+        mesh::{RenderMesh, RenderMeshBufferInfo, allocator::MeshAllocator},
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext, RenderLabel},
+        render_resource::{
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
+            BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePassDescriptor,
+            ComputePipelineDescriptor, PipelineCache, ShaderStages, ShaderType,
+            binding_types::{storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer},
+            encase::UniformBuffer,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        sync_world::MainEntity,
+        view::ExtractedView,
+    },
+    shader::ShaderDefVal,
+};
+
+use super::{
+    InstancePayload, RenderCustomMesh2dInstances,
+    culling::RenderInstanceCulling,
+    instance_material::{Custom2dPipeline, InstanceData},
+    transform,
+};
+
+/// Views an [`InstanceCulling::Gpu`](super::InstanceCulling::Gpu) batch can be culled against.
+/// Batches are drawn unculled when there are more.
+const MAX_CULLING_VIEWS: usize = 8;
+
+/// Workgroup size of the instance compute passes.
+pub(super) const WORKGROUP_SIZE: u32 = 64;
+
+/// Compute pipeline of [`InstanceCulling::Gpu`](super::InstanceCulling::Gpu), only present where
+/// it can be used.
+#[derive(Resource)]
+pub(super) struct GpuCullingPipeline<S, C> {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S: InstancePayload, C: InstancePayload> GpuCullingPipeline<S, C> {
+    /// Returns `None` if the device lacks compute shaders or the payloads can't be read as
+    /// floats.
+    pub(super) fn new(world: &World, shader: Handle<Shader>) -> Option<Self> {
+        let render_device = world.resource::<RenderDevice>();
+        let limits = render_device.limits();
+        if limits.max_compute_workgroup_size_x < WORKGROUP_SIZE
+            || limits.max_storage_buffers_per_shader_stage < 5
+        {
+            warn!(
+                "Compute shaders are not supported on this device, batches of {} and {} are drawn \
+                unculled",
+                std::any::type_name::<S>(),
+                std::any::type_name::<C>(),
+            );
+            return None;
+        }
+        if !size_of::<S>().is_multiple_of(4) || !size_of::<C>().is_multiple_of(4) {
+            warn!(
+                "GPU instance culling requires payload sizes that are multiples of 4 bytes, \
+                batches of {} and {} are drawn unculled",
+                std::any::type_name::<S>(),
+                std::any::type_name::<C>(),
+            );
+            return None;
+        }
+
+        let layout = render_device.create_bind_group_layout(
+            "gpu_instance_culling_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<GpuCullingParams>(false),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+
+        let mut shader_defs = vec![
+            ShaderDefVal::UInt("STATIC_INSTANCE_STRIDE".into(), (size_of::<S>() / 4) as u32),
+            ShaderDefVal::UInt(
+                "CHANGING_INSTANCE_STRIDE".into(),
+                (size_of::<C>() / 4) as u32,
+            ),
+            ShaderDefVal::UInt("CULLING_HALF_SPACES".into(), (MAX_CULLING_VIEWS * 6) as u32),
+        ];
+        if transform::is_affine::<C>() {
+            shader_defs.push("AFFINE_INSTANCES".into());
+        }
+        let pipeline =
+            world
+                .resource::<PipelineCache>()
+                .queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("gpu_instance_culling_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: vec![],
+                    shader,
+                    shader_defs,
+                    entry_point: Some("cull".into()),
+                    zero_initialize_workgroup_memory: true,
+                });
+
+        Some(GpuCullingPipeline {
+            layout,
+            pipeline,
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Path of the culling shader for the built-in payloads.
+pub(super) fn builtin_culling_shader() -> AssetPath<'static> {
+    AssetPath::from("embedded://fundamentals/vertex_buffer/culling.wgsl")
+}
+
+#[derive(ShaderType)]
+struct GpuCullingParams {
+    world_from_local: Mat4,
+    mesh_bounds: Vec4,
+    instance_count: u32,
+    view_count: u32,
+    half_spaces: [Vec4; MAX_CULLING_VIEWS * 6],
+}
+
+/// Visible instances of an [`InstanceCulling::Gpu`](super::InstanceCulling::Gpu) batch, written
+/// by the compute pass every frame.
+#[derive(Component)]
+pub(super) struct GpuCullingData<S: InstancePayload, C: InstancePayload> {
+    /// Capacity of the [`InstanceData`] buffers this was created for.
+    capacity: usize,
+    pub(super) buffers: [Buffer; 2],
+    pub(super) indirect_args: Buffer,
+    /// Binds `buffers` for drawing in storage buffer mode.
+    pub(super) storage_bind_group: Option<BindGroup>,
+    params: Buffer,
+    compute_bind_group: BindGroup,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S: InstancePayload, C: InstancePayload> GpuCullingData<S, C> {
+    fn new(
+        render_device: &RenderDevice,
+        pipeline: &Custom2dPipeline<S, C>,
+        culling_pipeline: &GpuCullingPipeline<S, C>,
+        instance_data: &InstanceData<S, C>,
+    ) -> Self {
+        let capacity = instance_data.capacity;
+        let create_buffer = |label: &'static str, size: u64, usage: BufferUsages| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: usage | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let usage = pipeline.instance_usage | BufferUsages::STORAGE;
+        let buffers = [
+            create_buffer(
+                "culled static instance data buffer",
+                (capacity * size_of::<S>()) as u64,
+                usage,
+            ),
+            create_buffer(
+                "culled changing instance data buffer",
+                (capacity * size_of::<C>()) as u64,
+                usage,
+            ),
+        ];
+        let indirect_args = create_buffer(
+            "instance indirect args buffer",
+            size_of::<[u32; 5]>() as u64,
+            BufferUsages::INDIRECT | BufferUsages::STORAGE,
+        );
+        let params = create_buffer(
+            "gpu instance culling params buffer",
+            GpuCullingParams::min_size().get(),
+            BufferUsages::UNIFORM,
+        );
+
+        let compute_bind_group = render_device.create_bind_group(
+            "gpu_instance_culling_bind_group",
+            &culling_pipeline.layout,
+            &BindGroupEntries::sequential((
+                params.as_entire_binding(),
+                instance_data.buffers[0].as_entire_binding(),
+                instance_data.buffers[1].as_entire_binding(),
+                buffers[0].as_entire_binding(),
+                buffers[1].as_entire_binding(),
+                indirect_args.as_entire_binding(),
+            )),
+        );
+        let storage_bind_group = pipeline.storage_layout.as_ref().map(|storage_layout| {
+            render_device.create_bind_group(
+                "culled_instance_storage_bind_group",
+                storage_layout,
+                &BindGroupEntries::sequential((
+                    buffers[0].as_entire_binding(),
+                    buffers[1].as_entire_binding(),
+                )),
+            )
+        });
+
+        GpuCullingData {
+            capacity,
+            buffers,
+            indirect_args,
+            storage_bind_group,
+            params,
+            compute_bind_group,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Compute passes to run this frame, shared by every payload type so they run once per frame
+/// rather than once per view.
+#[derive(Resource, Default)]
+pub(super) struct GpuCullingDispatches(Vec<ComputeDispatch>);
+
+/// A compute pass over the instances of one batch.
+pub(super) struct ComputeDispatch {
+    pub(super) pipeline: CachedComputePipelineId,
+    pub(super) bind_group: BindGroup,
+    pub(super) workgroups: u32,
+}
+
+/// Runs `dispatches` in a single compute pass, skipping those whose pipeline isn't compiled yet.
+pub(super) fn run_dispatches(
+    render_context: &mut RenderContext,
+    pipeline_cache: &PipelineCache,
+    dispatches: &[ComputeDispatch],
+    label: &str,
+) {
+    if dispatches.is_empty() {
+        return;
+    }
+
+    let mut pass = render_context
+        .command_encoder()
+        .begin_compute_pass(&ComputePassDescriptor {
+            label: Some(label),
+            timestamp_writes: None,
+        });
+    for dispatch in dispatches {
+        let Some(pipeline) = pipeline_cache.get_compute_pipeline(dispatch.pipeline) else {
+            continue;
+        };
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &dispatch.bind_group, &[]);
+        pass.dispatch_workgroups(dispatch.workgroups, 1, 1);
+    }
+}
+
+pub(super) fn clear_gpu_culling_dispatches(mut dispatches: ResMut<GpuCullingDispatches>) {
+    dispatches.0.clear();
+}
+
+/// Indirect draw args of the whole mesh with no instances, which the compute pass counts.
+fn indirect_args(
+    mesh_asset_id: AssetId<Mesh>,
+    render_meshes: &RenderAssets<RenderMesh>,
+    mesh_allocator: &MeshAllocator,
+) -> Option<[u32; 5]> {
+    let gpu_mesh = render_meshes.get(mesh_asset_id)?;
+    let vertex_slice = mesh_allocator.mesh_vertex_slice(&mesh_asset_id)?;
+    Some(match &gpu_mesh.buffer_info {
+        RenderMeshBufferInfo::Indexed { count, .. } => {
+            let index_slice = mesh_allocator.mesh_index_slice(&mesh_asset_id)?;
+            [
+                *count,
+                0,
+                index_slice.range.start,
+                vertex_slice.range.start,
+                0,
+            ]
+        }
+        RenderMeshBufferInfo::NonIndexed => [
+            vertex_slice.range.len() as u32,
+            0,
+            vertex_slice.range.start,
+            0,
+            0,
+        ],
+    })
+}
+
+pub(super) fn prepare_gpu_culling<S: InstancePayload, C: InstancePayload>(
+    mut commands: Commands,
+    query: Query<(
+        Entity,
+        &MainEntity,
+        &InstanceData<S, C>,
+        Option<&GpuCullingData<S, C>>,
+    )>,
+    render_mesh_instances: Res<RenderCustomMesh2dInstances<S, C>>,
+    instance_culling: Res<RenderInstanceCulling<S, C>>,
+    custom_pipeline: Res<Custom2dPipeline<S, C>>,
+    culling_pipeline: Option<Res<GpuCullingPipeline<S, C>>>,
+    (render_meshes, mesh_allocator): (Res<RenderAssets<RenderMesh>>, Res<MeshAllocator>),
+    views: Query<&Frustum, With<ExtractedView>>,
+    (render_device, render_queue): (Res<RenderDevice>, Res<RenderQueue>),
+    mut dispatches: ResMut<GpuCullingDispatches>,
+) {
+    let Some(culling_pipeline) = culling_pipeline else {
+        return;
+    };
+
+    let mut half_spaces = [Vec4::ZERO; MAX_CULLING_VIEWS * 6];
+    let mut view_count = 0;
+    if views.iter().len() <= MAX_CULLING_VIEWS {
+        for (view, frustum) in views.iter().enumerate() {
+            for (i, half_space) in frustum.half_spaces.iter().enumerate() {
+                half_spaces[view * 6 + i] = half_space.normal_d();
+            }
+            view_count += 1;
+        }
+    }
+
+    for (entity, main_entity, instance_data, culling_data) in &query {
+        let (Some(mesh_bounds), Some(mesh_instance)) = (
+            instance_culling.gpu_mesh_bounds(main_entity),
+            render_mesh_instances.get(main_entity),
+        ) else {
+            if culling_data.is_some() {
+                commands.entity(entity).remove::<GpuCullingData<S, C>>();
+            }
+            continue;
+        };
+        let Some(indirect_args) =
+            indirect_args(mesh_instance.mesh_asset_id, &render_meshes, &mesh_allocator)
+        else {
+            continue;
+        };
+
+        let new_culling_data = match culling_data {
+            Some(culling_data) if culling_data.capacity == instance_data.capacity => None,
+            _ => Some(GpuCullingData::new(
+                &render_device,
+                &custom_pipeline,
+                &culling_pipeline,
+                instance_data,
+            )),
+        };
+        let culling_data = new_culling_data.as_ref().or(culling_data).unwrap();
+
+        let mut params = UniformBuffer::new(Vec::new());
+        params
+            .write(&GpuCullingParams {
+                world_from_local: Affine3A::from(&mesh_instance.transforms.world_from_local).into(),
+                mesh_bounds: Vec4::new(
+                    mesh_bounds.min.x,
+                    mesh_bounds.min.y,
+                    mesh_bounds.max.x,
+                    mesh_bounds.max.y,
+                ),
+                instance_count: instance_data.length as u32,
+                view_count,
+                half_spaces,
+            })
+            .unwrap();
+        render_queue.write_buffer(&culling_data.params, 0, params.as_ref());
+        // Resets the instance count before the compute pass adds the visible instances.
+        render_queue.write_buffer(
+            &culling_data.indirect_args,
+            0,
+            bytemuck::cast_slice(&indirect_args),
+        );
+
+        dispatches.0.push(ComputeDispatch {
+            pipeline: culling_pipeline.pipeline,
+            bind_group: culling_data.compute_bind_group.clone(),
+            workgroups: (instance_data.length as u32).div_ceil(WORKGROUP_SIZE),
+        });
+
+        if let Some(culling_data) = new_culling_data {
+            commands.entity(entity).insert(culling_data);
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub(super) struct GpuCullingLabel;
+
+/// Runs the [`GpuCullingDispatches`] before any camera is rendered.
+#[derive(Default)]
+pub(super) struct GpuCullingNode;
+
+impl Node for GpuCullingNode {
+    fn run<'w>(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        // Batches draw nothing until the pipeline is compiled.
+        run_dispatches(
+            render_context,
+            world.resource::<PipelineCache>(),
+            &world.resource::<GpuCullingDispatches>().0,
+            "gpu_instance_culling",
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+// Batch uniform of the shaders given to `InstancingPlugin::with_shader`, at group 1.
+//
+// Merged batches are drawn together, each from its own range of instances, so the values of a
+// batch are looked up by instance in the vertex shader:
+//
+//     #import fundamentals::instance_batch::instance_batch
+//
+//     let batch = instance_batch(instance_index);
+
+#define_import_path fundamentals::instance_batch
+
+// Mirrored by `MAX_BATCH_RANGES` in `instance_material.rs`.
+const MAX_BATCH_RANGES: u32 = 32u;
+
+struct InstanceBatch {
+    // Zero for unused instances, which collapse to a point.
+    world_from_local: mat4x4f,
+    tint: vec4f,
+    // Free for custom shaders.
+    params: vec4f,
+    // Seconds since startup, wrapping around after an hour.
+    time: f32,
+    // End of the range of instances, which starts where the previous one ends.
+    instance_end: u32,
+    // Pads the struct to 128 bytes, like `InstanceBatchUniform`.
+    _padding: vec4u,
+};
+
+@group(1) @binding(0) var<uniform> instance_batches: array<InstanceBatch, MAX_BATCH_RANGES>;
+
+// Values of the batch the instance belongs to.
+fn instance_batch(instance_index: u32) -> InstanceBatch {
+    var index = 0u;
+    while index + 1u < MAX_BATCH_RANGES && instance_index >= instance_batches[index].instance_end {
+        index += 1u;
+    }
+    return instance_batches[index];
+}
+
+// Seconds since startup, the same for every batch, also available to fragment shaders.
+fn batch_time() -> f32 {
+    return instance_batches[0].time;
+}
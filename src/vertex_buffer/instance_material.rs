@@ -1,99 +1,565 @@
-use std::usize;
+use std::{marker::PhantomData, mem, num::NonZeroU64};
 
 use bevy::{
-    core_pipeline::core_2d::{CORE_2D_DEPTH_FORMAT, Transparent2d},
-    ecs::system::lifetimeless::{Read, SRes},
-    math::FloatOrd,
-    mesh::{PrimitiveTopology, VertexBufferLayout, VertexFormat},
+    asset::{AssetPath, load_embedded_asset},
+    camera::primitives::Frustum,
+    core_pipeline::core_2d::{
+        AlphaMask2d, AlphaMask2dBinKey, BatchSetKey2d, CORE_2D_DEPTH_FORMAT, Opaque2d,
+        Opaque2dBinKey, Transparent2d,
+    },
+    ecs::system::{
+        SystemChangeTick,
+        lifetimeless::{Read, SRes},
+    },
+    math::{Affine3A, FloatOrd},
+    mesh::{MeshVertexBufferLayoutRef, PrimitiveTopology, VertexBufferLayout},
+    platform::collections::{HashMap, HashSet},
     prelude::*,
     render::{
         Render, RenderApp, RenderSystems,
         extract_component::ExtractComponentPlugin,
-        extract_resource::ExtractResourcePlugin,
+        graph::CameraDriverLabel,
         mesh::{RenderMesh, RenderMeshBufferInfo, allocator::MeshAllocator},
         render_asset::RenderAssets,
+        render_graph::RenderGraph,
         render_phase::{
-            AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, SetItemPipeline,
-            ViewSortedRenderPhases,
+            AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem,
+            RenderCommand, SetItemPipeline, ViewBinnedRenderPhases, ViewSortedRenderPhases,
         },
         render_resource::{
-            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BlendState,
-            Buffer, BufferInitDescriptor, BufferUsages, ColorTargetState, ColorWrites,
-            DepthBiasState, DepthStencilState, FragmentState, FrontFace, PipelineCache,
-            PolygonMode, PrimitiveState, RenderPipelineDescriptor, ShaderStages,
-            SpecializedRenderPipeline, SpecializedRenderPipelines, StencilFaceState, StencilState,
-            TextureFormat, VertexAttribute, VertexState, VertexStepMode,
-            binding_types::uniform_buffer,
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BlendComponent,
+            BlendFactor, BlendOperation, BlendState, Buffer, BufferBinding, BufferDescriptor,
+            BufferId, BufferUsages, ColorTargetState, ColorWrites, DepthBiasState,
+            DepthStencilState, FragmentState, FrontFace, IndexFormat, PipelineCache, PolygonMode,
+            PrimitiveState, RawBufferVec, RenderPipelineDescriptor, SamplerBindingType, SamplerId,
+            ShaderStages, SpecializedMeshPipeline, SpecializedMeshPipelineError,
+            SpecializedMeshPipelines, StencilFaceState, StencilState, TextureFormat,
+            TextureSampleType, TextureViewId, VertexState,
+            binding_types::{
+                sampler, storage_buffer_read_only_sized, texture_2d, uniform_buffer,
+                uniform_buffer_sized,
+            },
         },
-        renderer::RenderDevice,
+        renderer::{RenderDevice, RenderQueue},
+        sync_world::MainEntity,
+        texture::{FallbackImage, GpuImage},
         view::{
             ExtractedView, RenderVisibleEntities, ViewTarget, ViewUniform, ViewUniformOffset,
             ViewUniforms,
         },
     },
-    sprite_render::{Mesh2dPipelineKey, RenderMesh2dInstances},
+    shader::ShaderDefVal,
+    sprite_render::Mesh2dPipelineKey,
 };
 
 use crate::vertex_buffer::RenderCustomMesh2dInstances;
 
-use super::{InstanceMaterialData, InstanceUniformData, SHADER_ASSET_PATH};
+use super::{
+    ATTRIBUTE_CUSTOM_POSITION, ATTRIBUTE_STROKE_OFFSET, InstanceBlendMode, InstanceMaterialData,
+    InstancePayload, InstanceTexture, InstanceUniformData, InstancingMode,
+    culling::{InstanceTransform, RenderInstanceCulling},
+    gpu_culling::{
+        GpuCullingData, GpuCullingDispatches, GpuCullingLabel, GpuCullingNode, GpuCullingPipeline,
+        clear_gpu_culling_dispatches, prepare_gpu_culling,
+    },
+    material::{InstancedMaterialPipeline, RenderInstancedMaterials, clear_instanced_materials},
+    merging::{BatchEntity, InstanceMergeGroups, MAX_MERGED_BATCHES, group_batches},
+    simulation::{
+        InstanceSimulationData, InstanceSimulationDispatches, InstanceSimulationLabel,
+        InstanceSimulationNode, InstanceSimulationPipeline, clear_instance_simulation_dispatches,
+        prepare_instance_simulation,
+    },
+    sorting::{back_to_front_order, gather},
+    transform,
+};
 
-pub(super) struct CustomMaterialPlugin;
+/// Render world setup shared by every [`InstancingPlugin`](super::InstancingPlugin).
+pub(super) struct SharedMaterialPlugin;
 
-impl Plugin for CustomMaterialPlugin {
+impl Plugin for SharedMaterialPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((ExtractResourcePlugin::<InstanceUniformData>::default(),));
+        app.add_plugins((
+            ExtractComponentPlugin::<InstanceUniformData>::default(),
+            ExtractComponentPlugin::<InstanceTexture>::default(),
+            ExtractComponentPlugin::<InstanceBlendMode>::default(),
+        ));
 
         let render_app = app.sub_app_mut(RenderApp);
-        render_app.add_render_command::<Transparent2d, DrawCustom>();
-        render_app.init_resource::<SpecializedRenderPipelines<Custom2dPipeline>>();
-        render_app.init_resource::<InstanceBuffer>();
+        render_app
+            .init_resource::<GpuCullingDispatches>()
+            .init_resource::<InstanceSimulationDispatches>()
+            .add_systems(
+                Render,
+                (
+                    clear_gpu_culling_dispatches,
+                    clear_instance_simulation_dispatches,
+                )
+                    .in_set(RenderSystems::PrepareResources),
+            );
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(GpuCullingLabel, GpuCullingNode);
+        render_graph.add_node_edge(GpuCullingLabel, CameraDriverLabel);
+        render_graph.add_node(InstanceSimulationLabel, InstanceSimulationNode);
+        render_graph.add_node_edge(InstanceSimulationLabel, GpuCullingLabel);
+    }
+}
+
+pub(super) struct CustomMaterialPlugin<S, C> {
+    pub(super) shader: Option<AssetPath<'static>>,
+    /// Compute shader of [`InstanceCulling::Gpu`](super::InstanceCulling::Gpu), which is
+    /// unavailable without one.
+    pub(super) culling_shader: Option<AssetPath<'static>>,
+    /// Compute shader updating the changing data of every batch, if any.
+    pub(super) simulation_shader: Option<AssetPath<'static>>,
+    pub(super) mode: InstancingMode,
+    pub(super) marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S: InstancePayload, C: InstancePayload> Plugin for CustomMaterialPlugin<S, C> {
+    fn build(&self, app: &mut App) {
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_render_command::<Transparent2d, DrawCustom<S, C>>()
+            .add_render_command::<Opaque2d, DrawCustom<S, C>>()
+            .add_render_command::<AlphaMask2d, DrawCustom<S, C>>()
+            .add_render_command::<Transparent2d, DrawCustomIndirect<S, C>>()
+            .add_render_command::<Opaque2d, DrawCustomIndirect<S, C>>()
+            .add_render_command::<AlphaMask2d, DrawCustomIndirect<S, C>>();
+        render_app.init_resource::<SpecializedMeshPipelines<Custom2dPipeline<S, C>>>();
+        render_app.init_resource::<InstanceBuffer<S, C>>();
+        render_app.init_resource::<RenderInstancedMaterials<S, C>>();
+        render_app.init_resource::<InstanceMergeGroups<S, C>>();
+        render_app.add_systems(ExtractSchedule, clear_instanced_materials::<S, C>);
         render_app
             // .add_systems(RenderStartup, init_custom_pipeline)
             .add_systems(
                 Render,
                 (
-                    queue_custom.in_set(RenderSystems::QueueMeshes),
+                    (group_batches::<S, C>, queue_custom::<S, C>)
+                        .chain()
+                        .in_set(RenderSystems::QueueMeshes),
                     // prepare_bind_group.in_set(RenderSystems::PrepareBindGroups),
-                    prepare_instance_buffers.in_set(RenderSystems::PrepareResources),
+                    (
+                        release_instance_buffers::<S, C>,
+                        prepare_instance_buffers::<S, C>,
+                    )
+                        .chain()
+                        .in_set(RenderSystems::PrepareResources),
+                    // Once the instance data of new batches is inserted.
+                    prepare_batch_uniforms::<S, C>.in_set(RenderSystems::PrepareBindGroups),
+                    prepare_texture_bind_groups::<S, C>.in_set(RenderSystems::PrepareBindGroups),
+                    prepare_gpu_culling::<S, C>.in_set(RenderSystems::PrepareBindGroups),
+                    prepare_instance_simulation::<S, C>.in_set(RenderSystems::PrepareBindGroups),
                 ),
             );
     }
 
     fn finish(&self, app: &mut App) {
+        let asset_server = app.world().resource::<AssetServer>();
+        let shader = self.shader.clone().map(|path| asset_server.load(path));
+        let culling_shader = self
+            .culling_shader
+            .clone()
+            .map(|path| asset_server.load(path));
+        let simulation_shader = self
+            .simulation_shader
+            .clone()
+            .map(|path| asset_server.load(path));
+
         let render_app = app.sub_app_mut(RenderApp);
-        render_app.init_resource::<Custom2dPipeline>();
+        let culling_pipeline = culling_shader
+            .and_then(|shader| GpuCullingPipeline::<S, C>::new(render_app.world(), shader));
+        let simulation_pipeline = simulation_shader
+            .and_then(|shader| InstanceSimulationPipeline::<S, C>::new(render_app.world(), shader));
+        let pipeline = Custom2dPipeline::<S, C>::new(
+            render_app.world(),
+            shader,
+            self.mode,
+            culling_pipeline.is_some() || simulation_pipeline.is_some(),
+        );
+        render_app.insert_resource(pipeline);
+        if let Some(culling_pipeline) = culling_pipeline {
+            render_app.insert_resource(culling_pipeline);
+        }
+        if let Some(simulation_pipeline) = simulation_pipeline {
+            render_app.insert_resource(simulation_pipeline);
+        }
     }
 }
 
-type DrawCustom = (
+type DrawCustom<S, C> = (
     SetItemPipeline,
-    SetCustomViewBindGroup<0>,
-    DrawMeshInstanced,
+    SetCustomViewBindGroup<S, C, 0>,
+    SetInstanceBatchBindGroup<S, C, 1>,
+    SetInstanceMaterialBindGroup<S, C, 2>,
+    DrawMeshInstanced<S, C>,
 );
 
-#[derive(Default, Resource)]
-pub struct InstanceBuffer {
+/// Draws the instances gathered by [`InstanceCulling::Gpu`](super::InstanceCulling::Gpu).
+type DrawCustomIndirect<S, C> = (
+    SetItemPipeline,
+    SetCustomViewBindGroup<S, C, 0>,
+    SetInstanceBatchBindGroup<S, C, 1>,
+    SetInstanceMaterialBindGroup<S, C, 2>,
+    DrawMeshInstancedIndirect<S, C>,
+);
+
+#[derive(Resource)]
+pub struct InstanceBuffer<S, C> {
     view_bind_group: Option<BindGroup>,
+    /// Ranges of every drawn [`InstanceData`], each starting at its
+    /// [`InstanceBatchUniformOffset`].
+    batch_uniforms: RawBufferVec<InstanceBatchUniform>,
+    /// Binds `batch_uniforms`, recreated when its buffer is.
+    batch_bind_group: Option<(BufferId, BindGroup)>,
+    marker: PhantomData<fn() -> (S, C)>,
 }
 
+impl<S, C> Default for InstanceBuffer<S, C> {
+    fn default() -> Self {
+        Self {
+            view_bind_group: None,
+            batch_uniforms: RawBufferVec::new(BufferUsages::UNIFORM),
+            batch_bind_group: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Ranges of instances bound for a draw, the size of the `instance_batches` array of
+/// `instance_batch.wgsl`. Each merged batch may be followed by unused instances.
+const MAX_BATCH_RANGES: usize = 2 * MAX_MERGED_BATCHES;
+
+/// Bytes bound for a draw.
+const BATCH_UNIFORM_SIZE: u64 = (MAX_BATCH_RANGES * size_of::<InstanceBatchUniform>()) as u64;
+
+/// Dynamic offsets of the batch uniform are a multiple of this, the largest alignment a device
+/// can require.
+const BATCH_UNIFORM_ALIGNMENT: usize = 256;
+
+/// Values shared by a range of instances of an [`InstanceData`], those of one
+/// [`InstanceMaterialData`], mirroring `InstanceBatch` in `instance_batch.wgsl`.
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct InstanceBatchUniform {
+    /// Includes the scale and offset of the [`InstanceUniformData`]. Zero for unused instances,
+    /// which collapse to a point.
+    world_from_local: [f32; 16],
+    tint: [f32; 4],
+    params: [f32; 4],
+    /// Seconds since startup, wrapping around like [`Time::elapsed_secs_wrapped`].
+    time: f32,
+    /// End of the range, which starts where the previous one ends.
+    instance_end: u32,
+    _padding: [u32; 6],
+}
+
+/// Dynamic offset of the ranges of the batch in [`InstanceBuffer::batch_uniforms`].
+#[derive(Component)]
+pub(super) struct InstanceBatchUniformOffset<S: InstancePayload, C: InstancePayload>(
+    u32,
+    PhantomData<fn() -> (S, C)>,
+);
+
+/// Binds the batch's [`InstanceTexture`], or a white fallback image.
+#[derive(Component)]
+pub(super) struct InstanceTextureBindGroup<S: InstancePayload, C: InstancePayload>(
+    pub(super) BindGroup,
+    PhantomData<fn() -> (S, C)>,
+);
+
+/// GPU side of an [`InstanceMaterialData`], or of all the batches merged with it, retained
+/// across frames.
+///
+/// Each batch keeps its range of the buffers while it fits, so only the batches that changed
+/// are uploaded again. Buffers are only reallocated when the number of instances exceeds
+/// `capacity`.
 #[derive(Component)]
-struct InstanceData {
-    buffers: [Buffer; 2],
-    length: usize,
+pub(super) struct InstanceData<S: InstancePayload, C: InstancePayload> {
+    pub(super) buffers: [Buffer; 2],
+    /// Binds `buffers` in [`InstancingMode::StorageBuffer`] mode.
+    bind_group: Option<BindGroup>,
+    pub(super) capacity: usize,
+    /// Number of instances drawn, including those left unused between the batches.
+    pub(super) length: usize,
+    /// Ranges of the buffers one after the other from the start, empty before the first upload.
+    uploaded: Vec<UploadedBatch>,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+/// Range of the [`InstanceData`] buffers reserved for a batch.
+struct UploadedBatch {
+    /// `None` once the batch is gone, leaving unused instances until the group is packed again.
+    batch: Option<BatchEntity>,
+    start: u32,
+    /// Instances reserved, as many as the batch has so culling never outgrows them.
+    capacity: u32,
+    /// Instances uploaded from `start`, the rest of the range is unused.
+    length: u32,
+    /// Revision of the uploaded static data.
+    static_revision: u32,
+    /// Indices of the uploaded instances in draw order, `None` if all were uploaded in order.
+    order: Option<Vec<u32>>,
+}
+
+impl UploadedBatch {
+    fn new(batch: BatchEntity, start: u32, capacity: u32) -> Self {
+        Self {
+            batch: Some(batch),
+            start,
+            capacity,
+            length: 0,
+            static_revision: 0,
+            order: None,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.start + self.capacity
+    }
+}
+
+/// Instances of a batch to upload, at the given indices or all of them in order.
+struct BatchUpload<'a, S: InstancePayload, C: InstancePayload> {
+    batch: BatchEntity,
+    data: &'a InstanceMaterialData<S, C>,
+    /// The batch changed since the last upload.
+    changed: bool,
+    order: Option<Vec<u32>>,
+}
+
+impl<S: InstancePayload, C: InstancePayload> BatchUpload<'_, S, C> {
+    fn len(&self) -> usize {
+        self.order.as_ref().map_or(self.data.len(), Vec::len)
+    }
+}
+
+impl<S: InstancePayload, C: InstancePayload> InstanceData<S, C> {
+    fn new(render_device: &RenderDevice, pipeline: &Custom2dPipeline<S, C>, length: usize) -> Self {
+        let capacity = length.max(1).next_power_of_two();
+        let usage = pipeline.instance_usage;
+        let create_buffer = |label: &'static str, stride: usize| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: (capacity * stride) as u64,
+                usage: usage | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let buffers = [
+            create_buffer("static instance data buffer", size_of::<S>()),
+            create_buffer("changing instance data buffer", size_of::<C>()),
+        ];
+        let bind_group = pipeline.storage_layout.as_ref().map(|storage_layout| {
+            render_device.create_bind_group(
+                "instance_storage_bind_group",
+                storage_layout,
+                &BindGroupEntries::sequential((
+                    buffers[0].as_entire_binding(),
+                    buffers[1].as_entire_binding(),
+                )),
+            )
+        });
+
+        InstanceData {
+            buffers,
+            bind_group,
+            capacity,
+            length,
+            uploaded: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Whether the instances of exactly these batches are uploaded.
+    fn holds(&self, batches: &[BatchEntity]) -> bool {
+        let uploaded = || self.uploaded.iter().filter_map(|range| range.batch);
+        uploaded().count() == batches.len()
+            && batches
+                .iter()
+                .all(|batch| uploaded().any(|uploaded| uploaded == *batch))
+    }
+
+    /// Uploads the instances of `batches`, which must fit in the buffers.
+    ///
+    /// Batches that still fit in their range are only uploaded when they changed, the others are
+    /// moved after the last range. The static data is only uploaded when it or the order changed.
+    /// Everything is packed again when the ranges no longer fit.
+    fn write(&mut self, render_queue: &RenderQueue, batches: Vec<BatchUpload<S, C>>) {
+        let mut ranges = mem::take(&mut self.uploaded);
+        for range in &mut ranges {
+            if range
+                .batch
+                .is_some_and(|uploaded| batches.iter().all(|batch| batch.batch != uploaded))
+            {
+                range.batch = None;
+            }
+        }
+
+        // Index of the range of each batch, and whether it moved.
+        let mut placed = Vec::with_capacity(batches.len());
+        for batch in &batches {
+            let capacity = batch.data.len() as u32;
+            let current = ranges
+                .iter()
+                .position(|range| range.batch == Some(batch.batch));
+            match current {
+                Some(index) if capacity <= ranges[index].capacity => placed.push((index, false)),
+                _ => {
+                    if let Some(index) = current {
+                        ranges[index].batch = None;
+                    }
+                    let start = ranges.last().map_or(0, UploadedBatch::end);
+                    ranges.push(UploadedBatch::new(batch.batch, start, capacity));
+                    placed.push((ranges.len() - 1, true));
+                }
+            }
+        }
+
+        let end = ranges.last().map_or(0, UploadedBatch::end);
+        if end as usize > self.capacity || batch_ranges(&ranges).len() > MAX_BATCH_RANGES {
+            ranges.clear();
+            placed.clear();
+            for batch in &batches {
+                let start = ranges.last().map_or(0, UploadedBatch::end);
+                ranges.push(UploadedBatch::new(
+                    batch.batch,
+                    start,
+                    batch.data.len() as u32,
+                ));
+                placed.push((ranges.len() - 1, true));
+            }
+        }
+
+        for ((index, moved), batch) in placed.into_iter().zip(batches) {
+            let range = &mut ranges[index];
+            let write_static = moved
+                || batch.data.static_revision != range.static_revision
+                || batch.order != range.order;
+            if write_static || batch.changed {
+                self.write_range(render_queue, range.start, &batch, write_static);
+            }
+            range.length = batch.len() as u32;
+            range.static_revision = batch.data.static_revision;
+            range.order = batch.order;
+        }
+
+        // Unused instances at the end are simply not drawn.
+        while ranges.last().is_some_and(|range| range.batch.is_none()) {
+            ranges.pop();
+        }
+        self.length = ranges
+            .last()
+            .map_or(0, |range| (range.start + range.length) as usize);
+        self.uploaded = ranges;
+    }
+
+    /// Uploads the instances of `batch` from `start`, along with their static data if
+    /// `write_static` is set.
+    fn write_range(
+        &self,
+        render_queue: &RenderQueue,
+        start: u32,
+        batch: &BatchUpload<S, C>,
+        write_static: bool,
+    ) {
+        let length = batch.data.len();
+        let static_data = &batch.data.static_data()[..length];
+        let changing_data = &batch.data.changing_data()[..length];
+        let static_offset = (start as usize * size_of::<S>()) as u64;
+        let changing_offset = (start as usize * size_of::<C>()) as u64;
+        match &batch.order {
+            // Instances in order need no copy.
+            None => {
+                if write_static {
+                    render_queue.write_buffer(
+                        &self.buffers[0],
+                        static_offset,
+                        bytemuck::cast_slice(static_data),
+                    );
+                }
+                render_queue.write_buffer(
+                    &self.buffers[1],
+                    changing_offset,
+                    bytemuck::cast_slice(changing_data),
+                );
+            }
+            Some(order) => {
+                if write_static {
+                    render_queue.write_buffer(
+                        &self.buffers[0],
+                        static_offset,
+                        bytemuck::cast_slice(&gather(static_data, order)),
+                    );
+                }
+                render_queue.write_buffer(
+                    &self.buffers[1],
+                    changing_offset,
+                    bytemuck::cast_slice(&gather(changing_data, order)),
+                );
+            }
+        }
+    }
+
+    /// Batch drawn by each range of instances with the end of the range, `None` for unused
+    /// instances.
+    pub(super) fn batch_ranges(&self) -> Vec<(Option<BatchEntity>, u32)> {
+        batch_ranges(&self.uploaded)
+    }
+}
+
+/// Splits `uploaded` into the ranges of the batches and the unused instances between them, up to
+/// the last instance drawn.
+fn batch_ranges(uploaded: &[UploadedBatch]) -> Vec<(Option<BatchEntity>, u32)> {
+    let mut ranges: Vec<(Option<BatchEntity>, u32)> = Vec::new();
+    let mut push = |batch: Option<BatchEntity>, end: u32| match ranges.last_mut() {
+        // Neighbouring unused instances share a range.
+        Some((None, last_end)) if batch.is_none() => *last_end = end,
+        _ => ranges.push((batch, end)),
+    };
+    for range in uploaded {
+        if let Some(batch) = range.batch {
+            push(Some(batch), range.start + range.length);
+        }
+        if range.batch.is_none() || range.length < range.capacity {
+            push(None, range.end());
+        }
+    }
+    if ranges.last().is_some_and(|(batch, _)| batch.is_none()) {
+        ranges.pop();
+    }
+    ranges
 }
 
 #[derive(Resource)]
-struct Custom2dPipeline {
+pub(super) struct Custom2dPipeline<S, C> {
     shader: Handle<Shader>,
     view_layout: BindGroupLayout,
+    batch_layout: BindGroupLayout,
+    /// Static and changing instance buffer layouts, with shader locations following the mesh's.
+    instance_layouts: [VertexBufferLayout; 2],
+    texture_layout: BindGroupLayout,
+    /// Static and changing storage buffers at group 3, in storage buffer mode.
+    pub(super) storage_layout: Option<BindGroupLayout>,
+    /// Usage of the instance buffers.
+    pub(super) instance_usage: BufferUsages,
+    /// Registered by each [`InstancedMaterial2dPlugin`](super::InstancedMaterial2dPlugin).
+    pub(super) materials: Vec<InstancedMaterialPipeline>,
+    marker: PhantomData<fn() -> (S, C)>,
     // mesh2d_pipeline: Mesh2dPipeline,
 }
 
-impl FromWorld for Custom2dPipeline {
-    fn from_world(world: &mut World) -> Self {
-        let asset_server = world.resource::<AssetServer>();
-        let shader = asset_server.load(SHADER_ASSET_PATH);
+impl<S: InstancePayload, C: InstancePayload> Custom2dPipeline<S, C> {
+    /// Uses the built-in `instancing.wgsl` when no `shader` is given.
+    /// With `compute_access`, instance buffers can also be bound by the culling and simulation
+    /// compute passes.
+    pub(super) fn new(
+        world: &World,
+        shader: Option<Handle<Shader>>,
+        mode: InstancingMode,
+        compute_access: bool,
+    ) -> Self {
+        let shader = shader.unwrap_or_else(|| load_embedded_asset!(world, "instancing.wgsl"));
 
         let render_device = world.resource::<RenderDevice>();
         let view_layout = render_device.create_bind_group_layout(
@@ -104,72 +570,278 @@ impl FromWorld for Custom2dPipeline {
             ),
         );
 
+        let batch_layout = render_device.create_bind_group_layout(
+            "instance_batch_layout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX_FRAGMENT,
+                uniform_buffer_sized(true, NonZeroU64::new(BATCH_UNIFORM_SIZE)),
+            ),
+        );
+
+        let texture_layout = render_device.create_bind_group_layout(
+            "instance_texture_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                ),
+            ),
+        );
+
+        let mode = match mode {
+            InstancingMode::StorageBuffer
+                if render_device.limits().max_storage_buffers_per_shader_stage < 2 =>
+            {
+                warn!(
+                    "Storage buffers are not supported on this device, \
+                    falling back to vertex buffer instancing"
+                );
+                InstancingMode::VertexBuffer
+            }
+            mode => mode,
+        };
+
+        let instance_layouts = instance_vertex_buffer_layouts::<S, C>(render_device, mode);
+
+        let storage_layout = (mode == InstancingMode::StorageBuffer).then(|| {
+            render_device.create_bind_group_layout(
+                "instance_storage_layout",
+                &BindGroupLayoutEntries::sequential(
+                    ShaderStages::VERTEX,
+                    (
+                        storage_buffer_read_only_sized(false, None),
+                        storage_buffer_read_only_sized(false, None),
+                    ),
+                ),
+            )
+        });
+
+        let mut instance_usage = match mode {
+            InstancingMode::VertexBuffer => BufferUsages::VERTEX,
+            InstancingMode::StorageBuffer => BufferUsages::STORAGE,
+        };
+        if compute_access {
+            instance_usage |= BufferUsages::STORAGE;
+        }
+
         Custom2dPipeline {
             shader,
             view_layout,
+            batch_layout,
+            instance_layouts,
+            texture_layout,
+            storage_layout,
+            instance_usage,
+            materials: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Shader locations reserved for the mesh: position, UV, normal and color, in that order.
+const MESH_ATTRIBUTE_LOCATIONS: u32 = 4;
+
+/// Builds the instance buffer layouts, panicking if they can't be used on this device.
+fn instance_vertex_buffer_layouts<S: InstancePayload, C: InstancePayload>(
+    render_device: &RenderDevice,
+    mode: InstancingMode,
+) -> [VertexBufferLayout; 2] {
+    for result in [S::validate_layout(), C::validate_layout()] {
+        if let Err(err) = result {
+            panic!("Invalid instance layout: {err}");
+        }
+    }
+
+    let static_layout = S::vertex_buffer_layout(MESH_ATTRIBUTE_LOCATIONS);
+    let changing_layout =
+        C::vertex_buffer_layout(MESH_ATTRIBUTE_LOCATIONS + static_layout.attributes.len() as u32);
+
+    if mode == InstancingMode::StorageBuffer {
+        assert!(
+            size_of::<S>().is_multiple_of(4) && size_of::<C>().is_multiple_of(4),
+            "Storage buffer instancing requires payload sizes that are multiples of 4 bytes"
+        );
+        return [static_layout, changing_layout];
+    }
+
+    let limits = render_device.limits();
+    let attribute_count = MESH_ATTRIBUTE_LOCATIONS as usize
+        + static_layout.attributes.len()
+        + changing_layout.attributes.len();
+    assert!(
+        attribute_count <= limits.max_vertex_attributes as usize,
+        "Instance layouts use {attribute_count} vertex attributes but the device only supports {}",
+        limits.max_vertex_attributes
+    );
+    for layout in [&static_layout, &changing_layout] {
+        assert!(
+            layout.array_stride <= limits.max_vertex_buffer_array_stride as u64,
+            "Instance stride of {} bytes exceeds the device limit of {}",
+            layout.array_stride,
+            limits.max_vertex_buffer_array_stride
+        );
+    }
+
+    [static_layout, changing_layout]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct Custom2dPipelineKey {
+    mesh_key: Mesh2dPipelineKey,
+    /// The batch has an [`InstanceTexture`], or a material and a mesh with UVs, so the mesh must
+    /// provide `Mesh::ATTRIBUTE_UV_0`.
+    textured: bool,
+    blend_mode: InstanceBlendMode,
+    /// Index of the batch's material in [`Custom2dPipeline::materials`].
+    material: Option<u32>,
+    /// Index format of indexed strip meshes, `None` for other meshes.
+    strip_index_format: Option<IndexFormat>,
+}
+
+impl InstanceBlendMode {
+    /// Opaque and masked batches write depth and are drawn before the transparent ones.
+    pub(super) fn writes_depth(self) -> bool {
+        matches!(self, InstanceBlendMode::Opaque | InstanceBlendMode::Mask)
+    }
+
+    fn blend_state(self) -> Option<BlendState> {
+        // Additive and multiplicative blending leave the target's alpha alone.
+        const KEEP_ALPHA: BlendComponent = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        match self {
+            InstanceBlendMode::Opaque | InstanceBlendMode::Mask => None,
+            InstanceBlendMode::Alpha => Some(BlendState::ALPHA_BLENDING),
+            InstanceBlendMode::Premultiplied => Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            InstanceBlendMode::Additive => Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: KEEP_ALPHA,
+            }),
+            // The shader fades the color towards white with `BLEND_MULTIPLY`.
+            InstanceBlendMode::Multiply => Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::Dst,
+                    dst_factor: BlendFactor::Zero,
+                    operation: BlendOperation::Add,
+                },
+                alpha: KEEP_ALPHA,
+            }),
         }
     }
 }
 
-impl SpecializedRenderPipeline for Custom2dPipeline {
-    type Key = Mesh2dPipelineKey;
-    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
-        let layout = vec![self.view_layout.clone()];
+impl<S: InstancePayload, C: InstancePayload> SpecializedMeshPipeline for Custom2dPipeline<S, C> {
+    type Key = Custom2dPipelineKey;
+    fn specialize(
+        &self,
+        key: Self::Key,
+        mesh_layout: &MeshVertexBufferLayoutRef,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let material = key.material.map(|slot| &self.materials[slot as usize]);
+        let mut layout = vec![
+            self.view_layout.clone(),
+            self.batch_layout.clone(),
+            material
+                .map_or(&self.texture_layout, |material| &material.layout)
+                .clone(),
+        ];
+        let vertex_shader = material
+            .and_then(|material| material.vertex_shader.clone())
+            .unwrap_or_else(|| self.shader.clone());
+        let fragment_shader = material
+            .and_then(|material| material.fragment_shader.clone())
+            .unwrap_or_else(|| self.shader.clone());
+        let mut shader_defs = Vec::new();
+
+        // 2D positions are preferred, Bevy's primitives only have 3D ones.
+        let mut vertex_attributes = Vec::new();
+        if mesh_layout.0.contains(ATTRIBUTE_CUSTOM_POSITION) {
+            vertex_attributes.push(ATTRIBUTE_CUSTOM_POSITION.at_shader_location(0));
+        } else {
+            vertex_attributes.push(Mesh::ATTRIBUTE_POSITION.at_shader_location(0));
+            shader_defs.push("VERTEX_POSITIONS_3D".into());
+        }
+        if key.textured {
+            vertex_attributes.push(Mesh::ATTRIBUTE_UV_0.at_shader_location(1));
+            shader_defs.push("VERTEX_UVS".into());
+        }
+        if mesh_layout.0.contains(Mesh::ATTRIBUTE_NORMAL) {
+            vertex_attributes.push(Mesh::ATTRIBUTE_NORMAL.at_shader_location(2));
+            shader_defs.push("VERTEX_NORMALS".into());
+        }
+        if mesh_layout.0.contains(Mesh::ATTRIBUTE_COLOR) {
+            vertex_attributes.push(Mesh::ATTRIBUTE_COLOR.at_shader_location(3));
+            shader_defs.push("VERTEX_COLORS".into());
+        }
+        if mesh_layout.0.contains(ATTRIBUTE_STROKE_OFFSET) {
+            // After the instance attributes, so the mesh's stroke doesn't move them.
+            let location = MESH_ATTRIBUTE_LOCATIONS
+                + self
+                    .instance_layouts
+                    .iter()
+                    .map(|layout| layout.attributes.len() as u32)
+                    .sum::<u32>();
+            vertex_attributes.push(ATTRIBUTE_STROKE_OFFSET.at_shader_location(location));
+            shader_defs.extend([
+                "VERTEX_STROKES".into(),
+                ShaderDefVal::UInt("STROKE_OFFSET_LOCATION".into(), location),
+            ]);
+        }
+        let mut buffers = vec![mesh_layout.0.get_layout(&vertex_attributes)?];
 
-        let format = if key.contains(Mesh2dPipelineKey::HDR) {
+        match key.blend_mode {
+            InstanceBlendMode::Mask => shader_defs.push("ALPHA_MASK".into()),
+            InstanceBlendMode::Multiply => shader_defs.push("BLEND_MULTIPLY".into()),
+            _ => {}
+        }
+        if transform::is_affine::<C>() {
+            shader_defs.push("AFFINE_INSTANCES".into());
+        }
+
+        match &self.storage_layout {
+            Some(storage_layout) => {
+                layout.push(storage_layout.clone());
+                shader_defs.extend([
+                    "STORAGE_INSTANCING".into(),
+                    ShaderDefVal::UInt(
+                        "STATIC_INSTANCE_STRIDE".into(),
+                        (size_of::<S>() / 4) as u32,
+                    ),
+                    ShaderDefVal::UInt(
+                        "CHANGING_INSTANCE_STRIDE".into(),
+                        (size_of::<C>() / 4) as u32,
+                    ),
+                ]);
+            }
+            None => buffers.extend(self.instance_layouts.iter().cloned()),
+        }
+
+        let format = if key.mesh_key.contains(Mesh2dPipelineKey::HDR) {
             ViewTarget::TEXTURE_FORMAT_HDR
         } else {
             TextureFormat::bevy_default()
         };
 
-        RenderPipelineDescriptor {
+        Ok(RenderPipelineDescriptor {
             label: Some("Custom2dRenderPipline".into()),
             layout,
             push_constant_ranges: vec![],
             vertex: VertexState {
-                shader: self.shader.clone(),
-                shader_defs: vec![],
+                shader: vertex_shader,
+                shader_defs: shader_defs.clone(),
                 entry_point: Some("vs".into()),
-                buffers: vec![
-                    VertexBufferLayout {
-                        step_mode: VertexStepMode::Vertex,
-                        array_stride: 2 * 4,
-                        attributes: vec![VertexAttribute {
-                            shader_location: 0,
-                            format: VertexFormat::Float32x2,
-                            offset: 0,
-                        }],
-                    },
-                    VertexBufferLayout {
-                        step_mode: VertexStepMode::Instance,
-                        array_stride: 6 * 4,
-                        attributes: vec![
-                            VertexAttribute {
-                                shader_location: 1,
-                                format: VertexFormat::Float32x4,
-                                offset: 0,
-                            },
-                            VertexAttribute {
-                                shader_location: 2,
-                                format: VertexFormat::Float32x2,
-                                offset: 4 * 4,
-                            },
-                        ],
-                    },
-                    VertexBufferLayout {
-                        step_mode: VertexStepMode::Instance,
-                        array_stride: 2 * 4,
-                        attributes: vec![VertexAttribute {
-                            shader_location: 3,
-                            format: VertexFormat::Float32x2,
-                            offset: 0,
-                        }],
-                    },
-                ],
+                buffers,
             },
             primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
+                topology: key.mesh_key.primitive_topology(),
+                strip_index_format: key.strip_index_format,
                 front_face: FrontFace::Ccw,
                 cull_mode: None,
                 unclipped_depth: false,
@@ -178,7 +850,7 @@ impl SpecializedRenderPipeline for Custom2dPipeline {
             },
             depth_stencil: Some(DepthStencilState {
                 format: CORE_2D_DEPTH_FORMAT,
-                depth_write_enabled: false,
+                depth_write_enabled: key.blend_mode.writes_depth(),
                 depth_compare: bevy::render::render_resource::CompareFunction::GreaterEqual,
                 stencil: StencilState {
                     front: StencilFaceState::IGNORE,
@@ -193,28 +865,30 @@ impl SpecializedRenderPipeline for Custom2dPipeline {
                 },
             }),
             multisample: bevy::render::render_resource::MultisampleState {
-                count: key.msaa_samples(),
+                count: key.mesh_key.msaa_samples(),
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             fragment: Some(FragmentState {
-                shader: self.shader.clone(),
-                shader_defs: vec![],
+                shader: fragment_shader,
+                shader_defs,
                 entry_point: Some("fs".into()),
                 targets: vec![Some(ColorTargetState {
                     format,
-                    blend: Some(BlendState::ALPHA_BLENDING),
+                    blend: key.blend_mode.blend_state(),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
             zero_initialize_workgroup_memory: true,
-        }
+        })
     }
 }
 
-pub struct SetCustomViewBindGroup<const I: usize>;
-impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetCustomViewBindGroup<I> {
-    type Param = SRes<InstanceBuffer>;
+pub struct SetCustomViewBindGroup<S, C, const I: usize>(PhantomData<fn() -> (S, C)>);
+impl<P: PhaseItem, S: InstancePayload, C: InstancePayload, const I: usize> RenderCommand<P>
+    for SetCustomViewBindGroup<S, C, I>
+{
+    type Param = SRes<InstanceBuffer<S, C>>;
     type ViewQuery = Read<ViewUniformOffset>;
     type ItemQuery = ();
 
@@ -236,22 +910,95 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetCustomViewBindGroup<I
     }
 }
 
-struct DrawMeshInstanced;
-impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+struct SetInstanceBatchBindGroup<S, C, const I: usize>(PhantomData<fn() -> (S, C)>);
+impl<P: PhaseItem, S: InstancePayload, C: InstancePayload, const I: usize> RenderCommand<P>
+    for SetInstanceBatchBindGroup<S, C, I>
+{
+    type Param = SRes<InstanceBuffer<S, C>>;
+    type ViewQuery = ();
+    type ItemQuery = Read<InstanceBatchUniformOffset<S, C>>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: bevy::ecs::query::ROQueryItem<'w, '_, Self::ViewQuery>,
+        batch_offset: Option<bevy::ecs::query::ROQueryItem<'w, '_, Self::ItemQuery>>,
+        param: bevy::ecs::system::SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut bevy::render::render_phase::TrackedRenderPass<'w>,
+    ) -> bevy::render::render_phase::RenderCommandResult {
+        let Some(batch_offset) = batch_offset else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+        if let Some((_, bind_group)) = &param.into_inner().batch_bind_group {
+            pass.set_bind_group(I, bind_group, &[batch_offset.0]);
+            bevy::render::render_phase::RenderCommandResult::Success
+        } else {
+            bevy::render::render_phase::RenderCommandResult::Failure(
+                "Failed to prepare batch bind group!",
+            )
+        }
+    }
+}
+
+struct SetInstanceMaterialBindGroup<S, C, const I: usize>(PhantomData<fn() -> (S, C)>);
+impl<P: PhaseItem, S: InstancePayload, C: InstancePayload, const I: usize> RenderCommand<P>
+    for SetInstanceMaterialBindGroup<S, C, I>
+{
+    type Param = SRes<RenderInstancedMaterials<S, C>>;
+    type ViewQuery = ();
+    type ItemQuery = Read<InstanceTextureBindGroup<S, C>>;
+
+    fn render<'w>(
+        item: &P,
+        _view: bevy::ecs::query::ROQueryItem<'w, '_, Self::ViewQuery>,
+        texture_bind_group: Option<bevy::ecs::query::ROQueryItem<'w, '_, Self::ItemQuery>>,
+        materials: bevy::ecs::system::SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut bevy::render::render_phase::TrackedRenderPass<'w>,
+    ) -> bevy::render::render_phase::RenderCommandResult {
+        let bind_group = match materials.into_inner().materials.get(&item.main_entity()) {
+            Some(material) => material.as_ref().map(|material| &material.bind_group),
+            // Missing while the texture is still loading.
+            None => texture_bind_group.map(|texture_bind_group| &texture_bind_group.0),
+        };
+        let Some(bind_group) = bind_group else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        bevy::render::render_phase::RenderCommandResult::Success
+    }
+}
+
+/// Mesh drawn for the phase item of `main_entity`.
+///
+/// Merged groups are queued under their leader as soon as any of their batches is visible, so
+/// the leader itself may be culled and missing from Bevy's `RenderMesh2dInstances`, which only
+/// holds visible meshes.
+pub(super) fn queued_mesh<S, C>(
+    render_mesh_instances: &RenderCustomMesh2dInstances<S, C>,
+    main_entity: &MainEntity,
+) -> Option<AssetId<Mesh>> {
+    render_mesh_instances
+        .get(main_entity)
+        .map(|mesh_instance| mesh_instance.mesh_asset_id)
+}
+
+struct DrawMeshInstanced<S, C>(PhantomData<fn() -> (S, C)>);
+impl<P: PhaseItem, S: InstancePayload, C: InstancePayload> RenderCommand<P>
+    for DrawMeshInstanced<S, C>
+{
     type Param = (
         SRes<RenderAssets<RenderMesh>>,
-        SRes<RenderMesh2dInstances>,
+        SRes<RenderCustomMesh2dInstances<S, C>>,
         SRes<MeshAllocator>,
     );
     type ViewQuery = ();
-    type ItemQuery = Read<InstanceData>;
+    type ItemQuery = Read<InstanceData<S, C>>;
 
     #[inline]
     fn render<'w>(
         item: &P,
         _view: bevy::ecs::query::ROQueryItem<'w, '_, Self::ViewQuery>,
         instance_data: Option<bevy::ecs::query::ROQueryItem<'w, '_, Self::ItemQuery>>,
-        (render_meshes, render_mesh2d_instances, mesh_allocator): bevy::ecs::system::SystemParamItem<
+        (render_meshes, render_mesh_instances, mesh_allocator): bevy::ecs::system::SystemParamItem<
             'w,
             '_,
             Self::Param,
@@ -260,17 +1007,14 @@ impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
     ) -> bevy::render::render_phase::RenderCommandResult {
         let mesh_allocator = mesh_allocator.into_inner();
 
-        let Some(mesh_instance) = render_mesh2d_instances.get(&item.main_entity()) else {
-            info_once!("1");
+        let Some(mesh_id) = queued_mesh(&render_mesh_instances, &item.main_entity()) else {
             return bevy::render::render_phase::RenderCommandResult::Skip;
         };
-        let Some(gpu_mesh) = render_meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+        let Some(gpu_mesh) = render_meshes.into_inner().get(mesh_id) else {
             info_once!("2");
             return bevy::render::render_phase::RenderCommandResult::Skip;
         };
-        let Some(vertex_buffer_slice) =
-            mesh_allocator.mesh_vertex_slice(&mesh_instance.mesh_asset_id)
-        else {
+        let Some(vertex_buffer_slice) = mesh_allocator.mesh_vertex_slice(&mesh_id) else {
             info_once!("3");
             return bevy::render::render_phase::RenderCommandResult::Skip;
         };
@@ -280,17 +1024,20 @@ impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
         };
 
         pass.set_vertex_buffer(0, vertex_buffer_slice.buffer.slice(..));
-        pass.set_vertex_buffer(1, instance_data.buffers[0].slice(..));
-        pass.set_vertex_buffer(2, instance_data.buffers[1].slice(..));
+        match &instance_data.bind_group {
+            Some(bind_group) => pass.set_bind_group(3, bind_group, &[]),
+            None => {
+                pass.set_vertex_buffer(1, instance_data.buffers[0].slice(..));
+                pass.set_vertex_buffer(2, instance_data.buffers[1].slice(..));
+            }
+        }
 
         match &gpu_mesh.buffer_info {
             RenderMeshBufferInfo::Indexed {
                 count,
                 index_format,
             } => {
-                let Some(index_buffer_slice) =
-                    mesh_allocator.mesh_index_slice(&mesh_instance.mesh_asset_id)
-                else {
+                let Some(index_buffer_slice) = mesh_allocator.mesh_index_slice(&mesh_id) else {
                     return bevy::render::render_phase::RenderCommandResult::Skip;
                 };
                 pass.set_index_buffer(index_buffer_slice.buffer.slice(..), 0, *index_format);
@@ -309,28 +1056,144 @@ impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
     }
 }
 
-fn queue_custom(
+struct DrawMeshInstancedIndirect<S, C>(PhantomData<fn() -> (S, C)>);
+impl<P: PhaseItem, S: InstancePayload, C: InstancePayload> RenderCommand<P>
+    for DrawMeshInstancedIndirect<S, C>
+{
+    type Param = (
+        SRes<RenderAssets<RenderMesh>>,
+        SRes<RenderCustomMesh2dInstances<S, C>>,
+        SRes<MeshAllocator>,
+    );
+    type ViewQuery = ();
+    type ItemQuery = Read<GpuCullingData<S, C>>;
+
+    #[inline]
+    fn render<'w>(
+        item: &P,
+        _view: bevy::ecs::query::ROQueryItem<'w, '_, Self::ViewQuery>,
+        culling_data: Option<bevy::ecs::query::ROQueryItem<'w, '_, Self::ItemQuery>>,
+        (render_meshes, render_mesh_instances, mesh_allocator): bevy::ecs::system::SystemParamItem<
+            'w,
+            '_,
+            Self::Param,
+        >,
+        pass: &mut bevy::render::render_phase::TrackedRenderPass<'w>,
+    ) -> bevy::render::render_phase::RenderCommandResult {
+        let mesh_allocator = mesh_allocator.into_inner();
+
+        let Some(mesh_id) = queued_mesh(&render_mesh_instances, &item.main_entity()) else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+        let Some(gpu_mesh) = render_meshes.into_inner().get(mesh_id) else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+        let Some(vertex_buffer_slice) = mesh_allocator.mesh_vertex_slice(&mesh_id) else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+        // Missing until the batch has been prepared for culling.
+        let Some(culling_data) = culling_data else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+
+        pass.set_vertex_buffer(0, vertex_buffer_slice.buffer.slice(..));
+        match &culling_data.storage_bind_group {
+            Some(bind_group) => pass.set_bind_group(3, bind_group, &[]),
+            None => {
+                pass.set_vertex_buffer(1, culling_data.buffers[0].slice(..));
+                pass.set_vertex_buffer(2, culling_data.buffers[1].slice(..));
+            }
+        }
+
+        // The mesh ranges are part of the indirect args.
+        match &gpu_mesh.buffer_info {
+            RenderMeshBufferInfo::Indexed { index_format, .. } => {
+                let Some(index_buffer_slice) = mesh_allocator.mesh_index_slice(&mesh_id) else {
+                    return bevy::render::render_phase::RenderCommandResult::Skip;
+                };
+                pass.set_index_buffer(index_buffer_slice.buffer.slice(..), 0, *index_format);
+                pass.draw_indexed_indirect(&culling_data.indirect_args, 0);
+            }
+            RenderMeshBufferInfo::NonIndexed => {
+                pass.draw_indirect(&culling_data.indirect_args, 0);
+            }
+        }
+
+        bevy::render::render_phase::RenderCommandResult::Success
+    }
+}
+
+fn queue_custom<S: InstancePayload, C: InstancePayload>(
     transparent_2d_draw_functions: Res<DrawFunctions<Transparent2d>>,
-    custom_pipline: Res<Custom2dPipeline>,
-    mut pipelines: ResMut<SpecializedRenderPipelines<Custom2dPipeline>>,
+    opaque_2d_draw_functions: Res<DrawFunctions<Opaque2d>>,
+    alpha_mask_2d_draw_functions: Res<DrawFunctions<AlphaMask2d>>,
+    custom_pipline: Res<Custom2dPipeline<S, C>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<Custom2dPipeline<S, C>>>,
     pipeline_cache: Res<PipelineCache>,
     render_meshes: Res<RenderAssets<RenderMesh>>,
-    render_mesh_instances: Res<RenderCustomMesh2dInstances>,
+    render_mesh_instances: Res<RenderCustomMesh2dInstances<S, C>>,
+    (materials, merge_groups): (
+        Res<RenderInstancedMaterials<S, C>>,
+        Res<InstanceMergeGroups<S, C>>,
+    ),
+    batches: Query<(Has<InstanceTexture>, Option<&InstanceBlendMode>)>,
     views: Query<(&RenderVisibleEntities, &ExtractedView, &Msaa)>,
     mut transparent_render_phases: ResMut<ViewSortedRenderPhases<Transparent2d>>,
+    mut opaque_render_phases: ResMut<ViewBinnedRenderPhases<Opaque2d>>,
+    mut alpha_mask_render_phases: ResMut<ViewBinnedRenderPhases<AlphaMask2d>>,
+    (instance_culling, culling_pipeline): (
+        Res<RenderInstanceCulling<S, C>>,
+        Option<Res<GpuCullingPipeline<S, C>>>,
+    ),
+    ticks: SystemChangeTick,
 ) {
+    // Indexed by whether the batch is drawn indirectly.
+    let draw_transparent = {
+        let draw_functions = transparent_2d_draw_functions.read();
+        [
+            draw_functions.id::<DrawCustom<S, C>>(),
+            draw_functions.id::<DrawCustomIndirect<S, C>>(),
+        ]
+    };
+    let draw_opaque = {
+        let draw_functions = opaque_2d_draw_functions.read();
+        [
+            draw_functions.id::<DrawCustom<S, C>>(),
+            draw_functions.id::<DrawCustomIndirect<S, C>>(),
+        ]
+    };
+    let draw_alpha_mask = {
+        let draw_functions = alpha_mask_2d_draw_functions.read();
+        [
+            draw_functions.id::<DrawCustom<S, C>>(),
+            draw_functions.id::<DrawCustomIndirect<S, C>>(),
+        ]
+    };
+
     for (visible_entities, view, msaa) in &views {
-        let Some(transparent_phase) = transparent_render_phases.get_mut(&view.retained_view_entity)
-        else {
+        let (Some(transparent_phase), Some(opaque_phase), Some(alpha_mask_phase)) = (
+            transparent_render_phases.get_mut(&view.retained_view_entity),
+            opaque_render_phases.get_mut(&view.retained_view_entity),
+            alpha_mask_render_phases.get_mut(&view.retained_view_entity),
+        ) else {
             continue;
         };
 
-        let draw_custom = transparent_2d_draw_functions.read().id::<DrawCustom>();
-
         let mesh_key = Mesh2dPipelineKey::from_msaa_samples(msaa.samples())
             | Mesh2dPipelineKey::from_hdr(view.hdr);
 
+        let mut queued_groups = HashSet::new();
         for (render_entity, visible_entity) in visible_entities.iter::<Mesh2d>() {
+            // A merged group is drawn once, by its leader, if any of its batches is visible.
+            let (render_entity, visible_entity) = match merge_groups.leaders.get(visible_entity) {
+                Some((leader, leader_main_entity)) => {
+                    if !queued_groups.insert(*leader_main_entity) {
+                        continue;
+                    }
+                    (leader, leader_main_entity)
+                }
+                None => (render_entity, visible_entity),
+            };
             if let Some(mesh_instance) = render_mesh_instances.get(visible_entity) {
                 let mesh2d_handle = mesh_instance.mesh_asset_id;
                 let mesh2d_transforms = &mesh_instance.transforms;
@@ -340,32 +1203,109 @@ fn queue_custom(
                     continue;
                 };
                 mesh2d_key |= Mesh2dPipelineKey::from_primitive_topology(mesh.primitive_topology());
+                let strip_index_format = match (mesh.primitive_topology(), &mesh.buffer_info) {
+                    (
+                        PrimitiveTopology::LineStrip | PrimitiveTopology::TriangleStrip,
+                        RenderMeshBufferInfo::Indexed { index_format, .. },
+                    ) => Some(*index_format),
+                    _ => None,
+                };
 
-                let pipline_id = pipelines.specialize(&pipeline_cache, &custom_pipline, mesh2d_key);
-
-                let mesh_z = mesh2d_transforms.world_from_local.translation.z;
-                transparent_phase.add(Transparent2d {
-                    sort_key: FloatOrd(mesh_z),
-                    entity: (*render_entity, *visible_entity),
-                    pipeline: pipline_id,
-                    draw_function: draw_custom,
-                    batch_range: 0..1,
-                    extracted_index: usize::MAX,
-                    extra_index: bevy::render::render_phase::PhaseItemExtraIndex::None,
+                let (textured, blend_mode) = batches.get(*render_entity).unwrap_or_default();
+                let (textured, material) = match materials.materials.get(visible_entity) {
+                    // Materials sample whatever they like, so UVs are used whenever available.
+                    Some(Some(material)) => (
+                        mesh.layout.0.contains(Mesh::ATTRIBUTE_UV_0),
+                        Some(material.slot),
+                    ),
+                    Some(None) => continue,
+                    None => (textured, None),
+                };
+                let key = Custom2dPipelineKey {
+                    mesh_key: mesh2d_key,
+                    textured,
+                    blend_mode: blend_mode.copied().unwrap_or_default(),
+                    material,
+                    strip_index_format,
+                };
+                let pipline_id =
+                    match pipelines.specialize(&pipeline_cache, &custom_pipline, key, &mesh.layout)
+                    {
+                        Ok(id) => id,
+                        Err(err) => {
+                            error!("{}", err);
+                            continue;
+                        }
+                    };
+
+                let indirect = (culling_pipeline.is_some()
+                    && instance_culling.gpu_mesh_bounds(visible_entity).is_some())
+                    as usize;
+
+                // Each batch is its own draw call, so the binned phases must not try to merge
+                // them with other meshes.
+                let batch_set_key = BatchSetKey2d {
                     indexed: mesh.indexed(),
-                });
+                };
+                match key.blend_mode {
+                    InstanceBlendMode::Opaque => opaque_phase.add(
+                        batch_set_key,
+                        Opaque2dBinKey {
+                            pipeline: pipline_id,
+                            draw_function: draw_opaque[indirect],
+                            asset_id: mesh2d_handle.into(),
+                            material_bind_group_id: mesh_instance.material_bind_group_id.0,
+                        },
+                        (*render_entity, *visible_entity),
+                        InputUniformIndex::default(),
+                        BinnedRenderPhaseType::NonMesh,
+                        ticks.this_run(),
+                    ),
+                    InstanceBlendMode::Mask => alpha_mask_phase.add(
+                        batch_set_key,
+                        AlphaMask2dBinKey {
+                            pipeline: pipline_id,
+                            draw_function: draw_alpha_mask[indirect],
+                            asset_id: mesh2d_handle.into(),
+                            material_bind_group_id: mesh_instance.material_bind_group_id.0,
+                        },
+                        (*render_entity, *visible_entity),
+                        InputUniformIndex::default(),
+                        BinnedRenderPhaseType::NonMesh,
+                        ticks.this_run(),
+                    ),
+                    _ => {
+                        let mesh_z = mesh2d_transforms.world_from_local.translation.z;
+                        transparent_phase.add(Transparent2d {
+                            sort_key: FloatOrd(mesh_z),
+                            entity: (*render_entity, *visible_entity),
+                            pipeline: pipline_id,
+                            draw_function: draw_transparent[indirect],
+                            batch_range: 0..1,
+                            extracted_index: usize::MAX,
+                            extra_index: bevy::render::render_phase::PhaseItemExtraIndex::None,
+                            indexed: mesh.indexed(),
+                        });
+                    }
+                }
             }
         }
     }
 }
 
-fn prepare_instance_buffers(
+pub(super) fn prepare_instance_buffers<S: InstancePayload, C: InstancePayload>(
     mut commands: Commands,
-    query: Query<(Entity, &InstanceMaterialData)>,
-    render_device: Res<RenderDevice>,
+    batches: Query<(Entity, &MainEntity, Ref<InstanceMaterialData<S, C>>)>,
+    mut instance_data_query: Query<&mut InstanceData<S, C>>,
+    merge_groups: Res<InstanceMergeGroups<S, C>>,
+    render_mesh_instances: Res<RenderCustomMesh2dInstances<S, C>>,
+    instance_culling: Res<RenderInstanceCulling<S, C>>,
+    instance_transform: Option<Res<InstanceTransform<S, C>>>,
+    views: Query<&Frustum, With<ExtractedView>>,
+    (render_device, render_queue): (Res<RenderDevice>, Res<RenderQueue>),
     view_uniforms: Res<ViewUniforms>,
-    custom_pipeline: Res<Custom2dPipeline>,
-    mut instance_buffer: ResMut<InstanceBuffer>,
+    custom_pipeline: Res<Custom2dPipeline<S, C>>,
+    mut instance_buffer: ResMut<InstanceBuffer<S, C>>,
 ) {
     if let Some(view_binding) = view_uniforms.uniforms.binding() {
         instance_buffer.view_bind_group = Some(render_device.create_bind_group(
@@ -375,22 +1315,275 @@ fn prepare_instance_buffers(
         ))
     }
 
-    for (entity, instance_data) in &query {
-        let static_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("instance data buffer"),
-            contents: bytemuck::cast_slice(instance_data.static_data.as_slice()),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-        });
+    for (entity, main_entity, instance_material_data) in &batches {
+        // Merged batches are uploaded by their leader, into its buffers.
+        let group = match merge_groups.leaders.get(main_entity) {
+            Some((_, leader)) if leader != main_entity => {
+                if instance_data_query.contains(entity) {
+                    commands.entity(entity).remove::<InstanceData<S, C>>();
+                }
+                continue;
+            }
+            Some(_) => merge_groups.groups[main_entity]
+                .iter()
+                .filter_map(|(entity, _)| batches.get(*entity).ok())
+                .collect(),
+            None => vec![(entity, main_entity, instance_material_data)],
+        };
+
+        let changed = group.iter().any(|(.., data)| data.is_changed());
+        let members: Vec<_> = group
+            .iter()
+            .map(|(entity, main_entity, instance_material_data)| {
+                // Culled batches depend on the views, so they are checked every frame.
+                let culled = instance_transform
+                    .as_ref()
+                    .zip(render_mesh_instances.get(*main_entity))
+                    .filter(|_| instance_culling.is_culled_on_cpu(main_entity));
+                ((*entity, **main_entity), instance_material_data, culled)
+            })
+            .collect();
+        let any_culled = members.iter().any(|(.., culled)| culled.is_some());
 
-        let changing_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("instance data buffer"),
-            contents: bytemuck::cast_slice(instance_data.changing_data.as_slice()),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        let instance_data = instance_data_query.get_mut(entity).ok();
+        let holds_group = instance_data.as_ref().is_some_and(|instance_data| {
+            let batches: Vec<_> = members.iter().map(|(batch, ..)| *batch).collect();
+            instance_data.holds(&batches)
         });
+        if !changed && !any_culled && holds_group {
+            continue;
+        }
 
-        commands.entity(entity).insert(InstanceData {
-            buffers: [static_buffer, changing_buffer],
-            length: instance_data.static_data.len(),
+        let uploads: Vec<_> = members
+            .into_iter()
+            .map(|(batch, instance_material_data, culled)| {
+                let main_entity = &batch.1;
+                let length = instance_material_data.len();
+                let sorted_order = (!instance_material_data.layers().is_empty())
+                    .then(|| back_to_front_order(instance_material_data.layers(), length));
+                let order = match culled {
+                    Some((instance_transform, mesh_instance)) => instance_culling
+                        .visible_instances(
+                            main_entity,
+                            instance_material_data,
+                            &Affine3A::from(&mesh_instance.transforms.world_from_local),
+                            instance_transform,
+                            views.iter(),
+                            sorted_order,
+                        ),
+                    None => sorted_order,
+                };
+                BatchUpload {
+                    batch,
+                    data: instance_material_data,
+                    changed: instance_material_data.is_changed(),
+                    order,
+                }
+            })
+            .collect();
+        let length = uploads.iter().map(|upload| upload.data.len()).sum();
+
+        match instance_data {
+            Some(mut instance_data) if instance_data.capacity >= length => {
+                instance_data.write(&render_queue, uploads);
+            }
+            _ => {
+                let mut instance_data =
+                    InstanceData::<S, C>::new(&render_device, &custom_pipeline, length);
+                instance_data.write(&render_queue, uploads);
+                commands.entity(entity).insert(instance_data);
+            }
+        }
+    }
+}
+
+/// Drops the GPU buffers of render entities that are no longer instanced.
+pub(super) fn release_instance_buffers<S: InstancePayload, C: InstancePayload>(
+    mut commands: Commands,
+    query: Query<
+        Entity,
+        (
+            With<InstanceData<S, C>>,
+            Without<InstanceMaterialData<S, C>>,
+        ),
+    >,
+) {
+    for entity in &query {
+        commands.entity(entity).remove::<(
+            InstanceData<S, C>,
+            GpuCullingData<S, C>,
+            InstanceSimulationData<S, C>,
+            InstanceBatchUniformOffset<S, C>,
+            InstanceTextureBindGroup<S, C>,
+        )>();
+    }
+}
+
+pub(super) fn prepare_batch_uniforms<S: InstancePayload, C: InstancePayload>(
+    mut commands: Commands,
+    mut query: Query<(
+        Entity,
+        &InstanceData<S, C>,
+        Option<&mut InstanceBatchUniformOffset<S, C>>,
+    )>,
+    uniforms: Query<&InstanceUniformData, With<InstanceMaterialData<S, C>>>,
+    render_mesh_instances: Res<RenderCustomMesh2dInstances<S, C>>,
+    time: Res<Time>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    custom_pipeline: Res<Custom2dPipeline<S, C>>,
+    mut instance_buffer: ResMut<InstanceBuffer<S, C>>,
+) {
+    let instance_buffer = instance_buffer.as_mut();
+    let batch_uniforms = &mut instance_buffer.batch_uniforms;
+    batch_uniforms.clear();
+    let time = time.elapsed_secs_wrapped();
+    let unused = |instance_end| InstanceBatchUniform {
+        time,
+        instance_end,
+        ..bytemuck::Zeroable::zeroed()
+    };
+
+    let mut last_start = None;
+    for (entity, instance_data, batch_offset) in &mut query {
+        // Each draw binds its ranges at a valid dynamic offset.
+        while !(batch_uniforms.len() * size_of::<InstanceBatchUniform>())
+            .is_multiple_of(BATCH_UNIFORM_ALIGNMENT)
+        {
+            batch_uniforms.push(unused(0));
+        }
+        last_start = Some(batch_uniforms.len());
+        let offset = (batch_uniforms.len() * size_of::<InstanceBatchUniform>()) as u32;
+
+        let mut ranges = instance_data.batch_ranges();
+        // The last range covers whatever is drawn, e.g. the instances gathered by GPU culling.
+        match ranges.last_mut() {
+            Some((_, instance_end)) => *instance_end = u32::MAX,
+            None => ranges.push((None, u32::MAX)),
+        }
+        for (batch, instance_end) in ranges {
+            let values = batch.and_then(|(entity, main_entity)| {
+                Some((
+                    uniforms.get(entity).ok()?,
+                    render_mesh_instances.get(&main_entity)?,
+                ))
+            });
+            batch_uniforms.push(match values {
+                Some((uniform, mesh_instance)) => InstanceBatchUniform {
+                    world_from_local: Mat4::from(Affine3A::from(
+                        &mesh_instance.transforms.world_from_local,
+                    ))
+                    .to_cols_array(),
+                    tint: uniform.tint.to_f32_array(),
+                    params: uniform.params.to_array(),
+                    time,
+                    instance_end,
+                    _padding: [0; 6],
+                },
+                None => unused(instance_end),
+            });
+        }
+
+        match batch_offset {
+            Some(mut batch_offset) => {
+                if batch_offset.0 != offset {
+                    batch_offset.0 = offset;
+                }
+            }
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(InstanceBatchUniformOffset::<S, C>(offset, PhantomData));
+            }
+        }
+    }
+    // Every offset binds a whole array.
+    if let Some(last_start) = last_start {
+        while batch_uniforms.len() < last_start + MAX_BATCH_RANGES {
+            batch_uniforms.push(unused(0));
+        }
+    }
+
+    batch_uniforms.write_buffer(&render_device, &render_queue);
+
+    let buffer_id = batch_uniforms.buffer().map(Buffer::id);
+    if buffer_id != instance_buffer.batch_bind_group.as_ref().map(|(id, _)| *id) {
+        instance_buffer.batch_bind_group = instance_buffer.batch_uniforms.buffer().map(|buffer| {
+            let bind_group = render_device.create_bind_group(
+                "instance_batch_bind_group",
+                &custom_pipeline.batch_layout,
+                &BindGroupEntries::single(BufferBinding {
+                    buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(BATCH_UNIFORM_SIZE),
+                }),
+            );
+            (buffer.id(), bind_group)
         });
     }
 }
+
+pub(super) fn prepare_texture_bind_groups<S: InstancePayload, C: InstancePayload>(
+    mut commands: Commands,
+    query: Query<
+        (
+            Entity,
+            &MainEntity,
+            Option<&InstanceTexture>,
+            Option<&InstanceTextureBindGroup<S, C>>,
+        ),
+        With<InstanceMaterialData<S, C>>,
+    >,
+    materials: Res<RenderInstancedMaterials<S, C>>,
+    render_device: Res<RenderDevice>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    fallback_image: Res<FallbackImage>,
+    custom_pipeline: Res<Custom2dPipeline<S, C>>,
+    mut bind_groups: Local<HashMap<Option<AssetId<Image>>, (TextureViewId, SamplerId, BindGroup)>>,
+) {
+    // Batches usually share a handful of atlases, so bind groups are shared between them and kept
+    // until their image is removed or replaced.
+    bind_groups
+        .retain(|image_id, _| image_id.is_none_or(|image_id| gpu_images.get(image_id).is_some()));
+
+    for (entity, main_entity, texture, current) in &query {
+        if materials.materials.contains_key(main_entity) {
+            continue;
+        }
+        let image_id = texture.map(|texture| texture.0.id());
+        let image = match image_id {
+            Some(image_id) => match gpu_images.get(image_id) {
+                Some(image) => image,
+                None => {
+                    if current.is_some() {
+                        commands
+                            .entity(entity)
+                            .remove::<InstanceTextureBindGroup<S, C>>();
+                    }
+                    continue;
+                }
+            },
+            None => &fallback_image.d2,
+        };
+        let ids = (image.texture_view.id(), image.sampler.id());
+        let bind_group = match bind_groups.get(&image_id) {
+            Some((view_id, sampler_id, bind_group)) if (*view_id, *sampler_id) == ids => {
+                bind_group.clone()
+            }
+            _ => {
+                let bind_group = render_device.create_bind_group(
+                    "instance_texture_bind_group",
+                    &custom_pipeline.texture_layout,
+                    &BindGroupEntries::sequential((&image.texture_view, &image.sampler)),
+                );
+                bind_groups.insert(image_id, (ids.0, ids.1, bind_group.clone()));
+                bind_group
+            }
+        };
+        if current.is_none_or(|current| current.0.id() != bind_group.id()) {
+            commands
+                .entity(entity)
+                .insert(InstanceTextureBindGroup::<S, C>(bind_group, PhantomData));
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// Bindings of the compute shader given to `InstancingPlugin::with_simulation_shader`.
+//
+// The shader imports this module and defines the entry point, run once per instance:
+//
+//     #import fundamentals::instance_simulation::{simulation, changing_data, changing_offset}
+//
+//     @compute @workgroup_size(64)
+//     fn update(@builtin(global_invocation_id) global_id: vec3u) { ... }
+
+#define_import_path fundamentals::instance_simulation
+
+struct SimulationParams {
+    // Seconds since startup, wrapping around like `Time::elapsed_secs_wrapped`.
+    time: f32,
+    // Seconds since the last frame.
+    delta_time: f32,
+    // Instances beyond this are unused and must not be written.
+    instance_count: u32,
+};
+
+@group(0) @binding(0) var<uniform> simulation: SimulationParams;
+// Read as plain floats, like the storage buffer mode of `instancing.wgsl`.
+@group(0) @binding(1) var<storage, read> static_data: array<f32>;
+@group(0) @binding(2) var<storage, read_write> changing_data: array<f32>;
+
+// Index of the first float of an instance in `static_data`.
+fn static_offset(instance: u32) -> u32 {
+    return instance * #{STATIC_INSTANCE_STRIDE}u;
+}
+
+// Index of the first float of an instance in `changing_data`.
+fn changing_offset(instance: u32) -> u32 {
+    return instance * #{CHANGING_INSTANCE_STRIDE}u;
+}
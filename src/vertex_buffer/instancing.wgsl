@@ -0,0 +1,153 @@
+#import bevy_render::view::View
+#import fundamentals::instance_batch::instance_batch
+
+@group(0) @binding(0) var<uniform> view: View;
+
+@group(2) @binding(0) var texture: texture_2d<f32>;
+@group(2) @binding(1) var texture_sampler: sampler;
+
+#ifdef STORAGE_INSTANCING
+// Read as plain floats so the packed `#[repr(C)]` layout of the Rust structs doesn't have to
+// follow WGSL struct alignment rules.
+@group(3) @binding(0) var<storage, read> static_data: array<f32>;
+@group(3) @binding(1) var<storage, read> changing_data: array<f32>;
+#endif
+
+struct Vertex {
+#ifdef VERTEX_POSITIONS_3D
+    @location(0) position: vec3f,
+#else
+    @location(0) position: vec2f,
+#endif
+#ifdef VERTEX_UVS
+    @location(1) uv: vec2f,
+#endif
+#ifdef VERTEX_NORMALS
+    @location(2) normal: vec3f,
+#endif
+#ifdef VERTEX_COLORS
+    @location(3) vertex_color: vec4f,
+#endif
+#ifndef STORAGE_INSTANCING
+    @location(4) color: vec4f,
+    @location(5) offset: vec2f,
+    @location(6) uv_rect: vec4f,
+#ifdef AFFINE_INSTANCES
+    @location(7) x_axis: vec2f,
+    @location(8) y_axis: vec2f,
+    @location(9) translation: vec2f,
+#else
+    @location(7) scale: vec2f,
+#endif
+#endif
+};
+
+struct Instance {
+    color: vec4f,
+    offset: vec2f,
+    uv_rect: vec4f,
+#ifdef AFFINE_INSTANCES
+    x_axis: vec2f,
+    y_axis: vec2f,
+    translation: vec2f,
+#else
+    scale: vec2f,
+#endif
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+#ifdef VERTEX_UVS
+    @location(1) uv: vec2f,
+#endif
+};
+
+#ifdef STORAGE_INSTANCING
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    let s = instance_index * #{STATIC_INSTANCE_STRIDE}u;
+    let c = instance_index * #{CHANGING_INSTANCE_STRIDE}u;
+
+    var instance: Instance;
+    instance.color = vec4f(
+        static_data[s], static_data[s + 1u], static_data[s + 2u], static_data[s + 3u]
+    );
+    instance.offset = vec2f(static_data[s + 4u], static_data[s + 5u]);
+    instance.uv_rect = vec4f(
+        static_data[s + 6u], static_data[s + 7u], static_data[s + 8u], static_data[s + 9u]
+    );
+#ifdef AFFINE_INSTANCES
+    instance.x_axis = vec2f(changing_data[c], changing_data[c + 1u]);
+    instance.y_axis = vec2f(changing_data[c + 2u], changing_data[c + 3u]);
+    instance.translation = vec2f(changing_data[c + 4u], changing_data[c + 5u]);
+#else
+    instance.scale = vec2f(changing_data[c], changing_data[c + 1u]);
+#endif
+    return instance;
+}
+#else
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    return Instance(
+        vertex.color,
+        vertex.offset,
+        vertex.uv_rect,
+#ifdef AFFINE_INSTANCES
+        vertex.x_axis,
+        vertex.y_axis,
+        vertex.translation,
+#else
+        vertex.scale,
+#endif
+    );
+}
+#endif
+
+@vertex
+fn vs(
+    vertex: Vertex,
+    @builtin(instance_index) instance_index: u32
+) -> VertexOutput {
+    let instance = load_instance(vertex, instance_index);
+    let batch = instance_batch(instance_index);
+
+    var vertex_output: VertexOutput;
+    // Instance offsets are in the entity's local space.
+#ifdef AFFINE_INSTANCES
+    let instance_position = instance.x_axis * vertex.position.x
+        + instance.y_axis * vertex.position.y
+        + instance.translation;
+#else
+    let instance_position = vertex.position.xy * instance.scale;
+#endif
+    let local_position = vec4f(instance_position + instance.offset, 0.0, 1.0);
+    let world_position = batch.world_from_local * local_position;
+    vertex_output.position = view.clip_from_world * world_position;
+    vertex_output.color = instance.color * batch.tint;
+#ifdef VERTEX_COLORS
+    vertex_output.color *= vertex.vertex_color;
+#endif
+#ifdef VERTEX_UVS
+    vertex_output.uv = mix(instance.uv_rect.xy, instance.uv_rect.zw, vertex.uv);
+#endif
+    return vertex_output;
+}
+
+@fragment
+fn fs(vertex_output: VertexOutput) -> @location(0) vec4f {
+#ifdef VERTEX_UVS
+    let color = vertex_output.color * textureSample(texture, texture_sampler, vertex_output.uv);
+#else
+    let color = vertex_output.color;
+#endif
+#ifdef ALPHA_MASK
+    if color.a < 0.5 {
+        discard;
+    }
+#endif
+#ifdef BLEND_MULTIPLY
+    // Transparent parts leave the target unchanged.
+    return vec4f(mix(vec3f(1.0), color.rgb, color.a), color.a);
+#else
+    return color;
+#endif
+}
@@ -0,0 +1,237 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    ecs::{
+        query::QueryItem,
+        system::{SystemParamItem, lifetimeless::SRes},
+    },
+    prelude::*,
+    render::{
+        Render, RenderApp, RenderSystems,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_asset::{
+            PrepareAssetError, RenderAsset, RenderAssetPlugin, RenderAssets, prepare_assets,
+        },
+        render_resource::{AsBindGroup, AsBindGroupError, BindGroup, BindGroupLayout},
+        renderer::RenderDevice,
+        sync_world::{MainEntity, MainEntityHashMap},
+    },
+    shader::ShaderRef,
+    sprite_render::Material2dBindGroupId,
+};
+
+use super::{
+    ChangingInstanceData, InstanceMaterialData, InstancePayload, InstancingPlugin,
+    RenderCustomMesh2dInstances, StaticInstanceData, instance_material::Custom2dPipeline,
+};
+
+/// Material of instanced batches, the counterpart of Bevy's `Material2d`.
+///
+/// The bind group of the material replaces the [`InstanceTexture`](super::InstanceTexture) one
+/// at group 2. Shaders keep the `vs` and `fs` entry points and the other groups of the
+/// plugin's shader, only the shader stages that differ have to be provided.
+pub trait InstancedMaterial2d: Asset + AsBindGroup + Clone + Sized {
+    /// Shader with the `vs` entry point, the plugin's shader by default.
+    fn vertex_shader() -> ShaderRef {
+        ShaderRef::Default
+    }
+
+    /// Shader with the `fs` entry point, the plugin's shader by default.
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Default
+    }
+}
+
+/// Draws an [`InstanceMaterialData`] batch with an [`InstancedMaterial2d`].
+#[derive(Component, Debug, Clone, PartialEq, Eq, Deref, DerefMut)]
+pub struct InstancedMeshMaterial2d<M: InstancedMaterial2d>(pub Handle<M>);
+
+impl<M: InstancedMaterial2d> ExtractComponent for InstancedMeshMaterial2d<M> {
+    type QueryData = &'static Self;
+    type QueryFilter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, '_, Self::QueryData>) -> Option<Self> {
+        Some(item.clone())
+    }
+}
+
+/// Lets batches of `S` and `C` use the material `M`.
+///
+/// Must be added after the [`InstancingPlugin`] of the same payloads.
+pub struct InstancedMaterial2dPlugin<M, S = StaticInstanceData, C = ChangingInstanceData>(
+    PhantomData<fn() -> (M, S, C)>,
+);
+
+impl<M, S, C> Default for InstancedMaterial2dPlugin<M, S, C> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<M: InstancedMaterial2d, S: InstancePayload, C: InstancePayload> Plugin
+    for InstancedMaterial2dPlugin<M, S, C>
+{
+    fn build(&self, app: &mut App) {
+        assert!(
+            app.is_plugin_added::<InstancingPlugin<S, C>>(),
+            "InstancedMaterial2dPlugin<{}> must be added after InstancingPlugin<{}, {}>",
+            std::any::type_name::<M>(),
+            std::any::type_name::<S>(),
+            std::any::type_name::<C>(),
+        );
+
+        // The material may be shared by several payload types.
+        if !app.is_plugin_added::<RenderAssetPlugin<PreparedInstancedMaterial2d<M>>>() {
+            app.init_asset::<M>().add_plugins((
+                ExtractComponentPlugin::<InstancedMeshMaterial2d<M>>::default(),
+                RenderAssetPlugin::<PreparedInstancedMaterial2d<M>>::default(),
+            ));
+        }
+
+        app.sub_app_mut(RenderApp).add_systems(
+            Render,
+            prepare_instanced_materials::<M, S, C>
+                .in_set(RenderSystems::PrepareAssets)
+                .after(prepare_assets::<PreparedInstancedMaterial2d<M>>),
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let asset_server = app.world().resource::<AssetServer>();
+        let load = |shader: ShaderRef| match shader {
+            ShaderRef::Default => None,
+            ShaderRef::Handle(handle) => Some(handle),
+            ShaderRef::Path(path) => Some(asset_server.load(path)),
+        };
+        let vertex_shader = load(M::vertex_shader());
+        let fragment_shader = load(M::fragment_shader());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        let layout = match render_app
+            .world()
+            .get_resource::<InstancedMaterial2dLayout<M>>()
+        {
+            Some(layout) => layout.0.clone(),
+            None => {
+                let layout = M::bind_group_layout(render_app.world().resource::<RenderDevice>());
+                render_app
+                    .insert_resource(InstancedMaterial2dLayout::<M>(layout.clone(), PhantomData));
+                layout
+            }
+        };
+
+        let mut pipeline = render_app
+            .world_mut()
+            .resource_mut::<Custom2dPipeline<S, C>>();
+        let slot = pipeline.materials.len() as u32;
+        pipeline.materials.push(InstancedMaterialPipeline {
+            layout,
+            vertex_shader,
+            fragment_shader,
+        });
+        render_app.insert_resource(InstancedMaterialSlot::<M, S, C>(slot, PhantomData));
+    }
+}
+
+/// Bind group layout and shaders of one [`InstancedMaterial2d`] type.
+pub(super) struct InstancedMaterialPipeline {
+    pub(super) layout: BindGroupLayout,
+    /// `None` to use the plugin's shader.
+    pub(super) vertex_shader: Option<Handle<Shader>>,
+    pub(super) fragment_shader: Option<Handle<Shader>>,
+}
+
+/// Index of `M` in [`Custom2dPipeline::materials`].
+#[derive(Resource)]
+struct InstancedMaterialSlot<M, S, C>(u32, PhantomData<fn() -> (M, S, C)>);
+
+#[derive(Resource)]
+pub(super) struct InstancedMaterial2dLayout<M>(BindGroupLayout, PhantomData<fn() -> M>);
+
+/// Bind group of an [`InstancedMaterial2d`].
+pub(super) struct PreparedInstancedMaterial2d<M> {
+    bind_group: BindGroup,
+    marker: PhantomData<fn() -> M>,
+}
+
+impl<M: InstancedMaterial2d> RenderAsset for PreparedInstancedMaterial2d<M> {
+    type SourceAsset = M;
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<InstancedMaterial2dLayout<M>>,
+        M::Param,
+    );
+
+    fn prepare_asset(
+        material: Self::SourceAsset,
+        _: AssetId<Self::SourceAsset>,
+        (render_device, layout, material_param): &mut SystemParamItem<Self::Param>,
+        _: Option<&Self>,
+    ) -> Result<Self, PrepareAssetError<Self::SourceAsset>> {
+        match material.as_bind_group(&layout.0, render_device, material_param) {
+            Ok(prepared) => Ok(PreparedInstancedMaterial2d {
+                bind_group: prepared.bind_group,
+                marker: PhantomData,
+            }),
+            Err(AsBindGroupError::RetryNextUpdate) => {
+                Err(PrepareAssetError::RetryNextUpdate(material))
+            }
+            Err(err) => Err(PrepareAssetError::AsBindGroupError(err)),
+        }
+    }
+}
+
+/// Material of a batch, bound at group 2 instead of its texture.
+pub(super) struct RenderInstancedMaterial {
+    /// Index in [`Custom2dPipeline::materials`].
+    pub(super) slot: u32,
+    pub(super) bind_group: BindGroup,
+}
+
+/// Materials of the batches that have one, rebuilt every frame.
+///
+/// Entries are `None` while the material isn't prepared, and such batches aren't drawn.
+#[derive(Resource)]
+pub(super) struct RenderInstancedMaterials<S, C> {
+    pub(super) materials: MainEntityHashMap<Option<RenderInstancedMaterial>>,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S, C> Default for RenderInstancedMaterials<S, C> {
+    fn default() -> Self {
+        Self {
+            materials: default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+pub(super) fn clear_instanced_materials<S: InstancePayload, C: InstancePayload>(
+    mut materials: ResMut<RenderInstancedMaterials<S, C>>,
+) {
+    materials.materials.clear();
+}
+
+fn prepare_instanced_materials<M: InstancedMaterial2d, S: InstancePayload, C: InstancePayload>(
+    query: Query<(&MainEntity, &InstancedMeshMaterial2d<M>), With<InstanceMaterialData<S, C>>>,
+    prepared_materials: Res<RenderAssets<PreparedInstancedMaterial2d<M>>>,
+    slot: Res<InstancedMaterialSlot<M, S, C>>,
+    mut materials: ResMut<RenderInstancedMaterials<S, C>>,
+    mut render_mesh_instances: ResMut<RenderCustomMesh2dInstances<S, C>>,
+) {
+    for (main_entity, material) in &query {
+        let material =
+            prepared_materials
+                .get(material.id())
+                .map(|prepared| RenderInstancedMaterial {
+                    slot: slot.0,
+                    bind_group: prepared.bind_group.clone(),
+                });
+        if let Some(mesh_instance) = render_mesh_instances.get_mut(main_entity) {
+            mesh_instance.material_bind_group_id =
+                Material2dBindGroupId(material.as_ref().map(|material| material.bind_group.id()));
+        }
+        materials.materials.insert(*main_entity, material);
+    }
+}
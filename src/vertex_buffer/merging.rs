@@ -0,0 +1,213 @@
+use std::{hash::Hash, marker::PhantomData};
+
+use bevy::{
+    platform::collections::HashMap,
+    prelude::*,
+    render::{
+        render_resource::BindGroupId,
+        sync_world::{MainEntity, MainEntityHashMap},
+    },
+};
+
+use super::{
+    InstanceBlendMode, InstanceMaterialData, InstancePayload, InstanceTexture,
+    RenderCustomMesh2dInstances, culling::RenderInstanceCulling,
+    material::RenderInstancedMaterials, simulation::InstanceSimulationPipeline,
+};
+
+/// Most batches drawn together, so that each fits in the batch uniform of `instance_batch.wgsl`
+/// along with the unused instances after it.
+pub(super) const MAX_MERGED_BATCHES: usize = 16;
+
+/// What batches must share to be drawn together.
+///
+/// Their transforms and [`InstanceUniformData`](super::InstanceUniformData) can differ, each
+/// batch has its own entry in the batch uniform.
+#[derive(PartialEq, Eq, Hash, Debug)]
+struct MergeKey {
+    mesh: AssetId<Mesh>,
+    texture: Option<AssetId<Image>>,
+    material: Option<BindGroupId>,
+    blend_mode: InstanceBlendMode,
+    /// Bits of the depth of blended batches, which are sorted by it. The other batches are
+    /// ordered by the depth buffer and merge at any depth.
+    depth: Option<u32>,
+}
+
+impl MergeKey {
+    fn new(
+        mesh: AssetId<Mesh>,
+        texture: Option<AssetId<Image>>,
+        material: Option<BindGroupId>,
+        blend_mode: InstanceBlendMode,
+        depth: f32,
+    ) -> Self {
+        Self {
+            mesh,
+            texture,
+            material,
+            blend_mode,
+            depth: (!blend_mode.writes_depth()).then_some(depth.to_bits()),
+        }
+    }
+}
+
+/// Render entity and main entity of a batch.
+pub(super) type BatchEntity = (Entity, MainEntity);
+
+/// Batches drawn together with others, rebuilt every frame.
+///
+/// Each group is drawn by its first batch, the leader, which holds the instances of the whole
+/// group in its [`InstanceData`](super::instance_material::InstanceData), each batch in its own
+/// range. Groups are packed again when their leader changes.
+#[derive(Resource)]
+pub(super) struct InstanceMergeGroups<S, C> {
+    /// Leader of every merged batch, including the leaders themselves.
+    pub(super) leaders: MainEntityHashMap<BatchEntity>,
+    /// Batches of each group in draw order, keyed by the leader.
+    pub(super) groups: MainEntityHashMap<Vec<BatchEntity>>,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S, C> Default for InstanceMergeGroups<S, C> {
+    fn default() -> Self {
+        Self {
+            leaders: default(),
+            groups: default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Splits `batches` by key into sorted groups of two to [`MAX_MERGED_BATCHES`].
+fn merge_groups<K: Eq + Hash, T: Ord + Clone>(
+    batches: impl IntoIterator<Item = (K, T)>,
+) -> Vec<Vec<T>> {
+    let mut groups: HashMap<K, Vec<T>> = HashMap::default();
+    for (key, batch) in batches {
+        groups.entry(key).or_default().push(batch);
+    }
+    let mut groups: Vec<Vec<T>> = groups
+        .into_values()
+        .flat_map(|mut group| {
+            group.sort();
+            group
+                .chunks(MAX_MERGED_BATCHES)
+                .filter(|group| group.len() > 1)
+                .map(<[T]>::to_vec)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+/// Groups the batches that can be drawn with a single call.
+///
+/// Batches culled on the GPU and simulated batches work on their own buffers, so they are never
+/// merged.
+pub(super) fn group_batches<S: InstancePayload, C: InstancePayload>(
+    batches: Query<
+        (
+            Entity,
+            &MainEntity,
+            Option<&InstanceTexture>,
+            Option<&InstanceBlendMode>,
+        ),
+        With<InstanceMaterialData<S, C>>,
+    >,
+    render_mesh_instances: Res<RenderCustomMesh2dInstances<S, C>>,
+    instance_culling: Res<RenderInstanceCulling<S, C>>,
+    materials: Res<RenderInstancedMaterials<S, C>>,
+    simulation_pipeline: Option<Res<InstanceSimulationPipeline<S, C>>>,
+    mut merged: ResMut<InstanceMergeGroups<S, C>>,
+) {
+    let merged = merged.as_mut();
+    merged.leaders.clear();
+    merged.groups.clear();
+    if simulation_pipeline.is_some() {
+        return;
+    }
+
+    let keyed_batches = batches
+        .iter()
+        .filter_map(|(entity, main_entity, texture, blend_mode)| {
+            if instance_culling.gpu_mesh_bounds(main_entity).is_some() {
+                return None;
+            }
+            let material = match materials.materials.get(main_entity) {
+                Some(Some(material)) => Some(material.bind_group.id()),
+                // Not drawn at all.
+                Some(None) => return None,
+                None => None,
+            };
+            let mesh_instance = render_mesh_instances.get(main_entity)?;
+            let key = MergeKey::new(
+                mesh_instance.mesh_asset_id,
+                texture.map(|texture| texture.0.id()),
+                material,
+                blend_mode.copied().unwrap_or_default(),
+                mesh_instance.transforms.world_from_local.translation.z,
+            );
+            Some((key, (*main_entity, entity)))
+        });
+
+    for group in merge_groups(keyed_batches) {
+        let group: Vec<BatchEntity> = group
+            .into_iter()
+            .map(|(main_entity, entity)| (entity, main_entity))
+            .collect();
+        let leader = group[0];
+        for (_, main_entity) in &group {
+            merged.leaders.insert(*main_entity, leader);
+        }
+        merged.groups.insert(leader.1, group);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_are_grouped_by_key() {
+        let groups = merge_groups([("a", 3), ("b", 1), ("a", 2), ("c", 4), ("b", 0), ("a", 5)]);
+        assert_eq!(groups, [vec![0, 1], vec![2, 3, 5]]);
+    }
+
+    #[test]
+    fn lone_batches_are_not_grouped() {
+        assert!(merge_groups([("a", 0), ("b", 1), ("c", 2)]).is_empty());
+        assert!(merge_groups::<&str, u32>([]).is_empty());
+    }
+
+    #[test]
+    fn large_groups_are_split() {
+        let batches = (0..2 * MAX_MERGED_BATCHES + 1).map(|i| ("a", i));
+        let groups = merge_groups(batches);
+        assert_eq!(groups.len(), 2);
+        assert!(groups.iter().all(|group| group.len() == MAX_MERGED_BATCHES));
+    }
+
+    #[test]
+    fn only_blended_batches_merge_at_the_same_depth() {
+        let key =
+            |blend_mode, depth| MergeKey::new(AssetId::default(), None, None, blend_mode, depth);
+        assert_eq!(
+            key(InstanceBlendMode::Opaque, 0.0),
+            key(InstanceBlendMode::Opaque, 1.0)
+        );
+        assert_eq!(
+            key(InstanceBlendMode::Mask, 0.0),
+            key(InstanceBlendMode::Mask, 1.0)
+        );
+        assert_eq!(
+            key(InstanceBlendMode::Alpha, 1.0),
+            key(InstanceBlendMode::Alpha, 1.0)
+        );
+        assert_ne!(
+            key(InstanceBlendMode::Alpha, 0.0),
+            key(InstanceBlendMode::Alpha, 1.0)
+        );
+    }
+}
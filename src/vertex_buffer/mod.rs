@@ -1,201 +1,594 @@
+mod arc;
+mod attributes;
+mod culling;
+mod gpu_culling;
 mod instance_material;
+mod material;
+mod merging;
+mod sdf;
+mod simulation;
+mod sorting;
+mod stroke;
+mod transform;
+
+use std::{
+    any::TypeId,
+    marker::PhantomData,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use bevy::{
-    asset::RenderAssetUsages,
-    camera::visibility::NoFrustumCulling,
+    asset::{AssetPath, embedded_asset},
+    camera::visibility::VisibilitySystems,
+    math::{Affine2, Affine3A},
     mesh::{MeshVertexAttribute, VertexFormat},
     prelude::*,
     render::{
         Extract, RenderApp,
         extract_component::ExtractComponent,
-        extract_resource::ExtractResource,
-        render_resource::ShaderType,
         sync_component::SyncComponentPlugin,
-        sync_world::{MainEntityHashMap, RenderEntity},
+        sync_world::{MainEntity, MainEntityHashMap, RenderEntity},
     },
+    shader::load_shader_library,
+    sprite::calculate_bounds_2d,
     sprite_render::{
         Material2dBindGroupId, Mesh2dTransforms, MeshFlags, RenderMesh2dInstance, extract_mesh2d,
     },
-    window::PrimaryWindow,
 };
-use rand::{Rng, SeedableRng};
 
-const SHADER_ASSET_PATH: &str = "shaders/instancing.wgsl";
+pub use arc::ArcInstanceData;
+pub use attributes::{InstanceAttribute, InstanceAttributes, InstancePayload};
+pub use culling::InstanceCulling;
+pub use material::{InstancedMaterial2d, InstancedMaterial2dPlugin, InstancedMeshMaterial2d};
+pub use sdf::SdfInstanceData;
+pub use stroke::StrokedInstanceData;
+pub use transform::{AffineInstanceData, PackedAffine2, Transform2d};
 
-const ATTRIBUTE_CUSTOM_POSITION: MeshVertexAttribute =
+/// 2D vertex position attribute, used instead of `Mesh::ATTRIBUTE_POSITION` when a mesh has it.
+pub const ATTRIBUTE_CUSTOM_POSITION: MeshVertexAttribute =
     MeshVertexAttribute::new("Position", 988540917, VertexFormat::Float32x2);
 
-pub struct InstancingPlugin;
+/// Stroke vertex attribute, see [`Shape2d::with_stroke`](crate::Shape2d::with_stroke).
+///
+/// `xy` is how far the vertex moves out per unit of [`StrokedInstanceData::stroke_width`], and `z`
+/// is 1 on stroke vertices and 0 on fill ones.
+pub const ATTRIBUTE_STROKE_OFFSET: MeshVertexAttribute =
+    MeshVertexAttribute::new("StrokeOffset", 988540918, VertexFormat::Float32x3);
+
+/// Renders entities with a `Mesh2d` and an [`InstanceMaterialData<S, C>`] using instancing.
+///
+/// Add one plugin per pair of payload types. Payloads other than [`StaticInstanceData`] with
+/// [`ChangingInstanceData`] or [`AffineInstanceData`] need a shader whose vertex inputs match
+/// their [`InstanceAttributes`], see [`InstancingPlugin::with_shader`].
+pub struct InstancingPlugin<S = StaticInstanceData, C = ChangingInstanceData> {
+    shader: Option<AssetPath<'static>>,
+    culling_shader: Option<AssetPath<'static>>,
+    simulation_shader: Option<AssetPath<'static>>,
+    mode: InstancingMode,
+    instance_transform: Option<fn(&S, &C) -> Affine2>,
+    stroke_width: Option<fn(&S, &C) -> f32>,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S, C> Default for InstancingPlugin<S, C> {
+    fn default() -> Self {
+        Self {
+            shader: None,
+            culling_shader: None,
+            simulation_shader: None,
+            mode: InstancingMode::default(),
+            instance_transform: None,
+            stroke_width: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl InstancingPlugin {
+    /// Plugin for the built-in [`StaticInstanceData`] and [`ChangingInstanceData`] payloads.
+    pub fn new() -> Self {
+        Self::default()
+            .with_instance_transform(culling::builtin_instance_transform)
+            .with_culling_shader(gpu_culling::builtin_culling_shader())
+    }
+}
+
+impl InstancingPlugin<StaticInstanceData, AffineInstanceData> {
+    /// Plugin for [`StaticInstanceData`] with an [`AffineInstanceData`] instead of a
+    /// [`ChangingInstanceData`], so instances can also be rotated and sheared.
+    ///
+    /// Shaders are compiled with `AFFINE_INSTANCES`, which `instancing.wgsl` and the built-in
+    /// culling shader read the transform with.
+    pub fn affine() -> Self {
+        Self::default()
+            .with_instance_transform(culling::affine_instance_transform)
+            .with_culling_shader(gpu_culling::builtin_culling_shader())
+    }
+}
+
+impl InstancingPlugin<ArcInstanceData> {
+    /// Plugin drawing an [`ArcInstanceData`] arc per instance with the built-in `arc.wgsl`.
+    ///
+    /// Batches are expected to use a [`Shape2d::arc_template`](crate::Shape2d::arc_template)
+    /// mesh and can't be textured. [`InstanceCulling::Gpu`] needs a culling shader adapted to the
+    /// template, see [`InstancingPlugin::with_culling_shader`].
+    pub fn arcs() -> Self {
+        Self::default()
+            .with_shader("embedded://fundamentals/vertex_buffer/arc.wgsl")
+            .with_instance_transform(arc::arc_instance_transform)
+    }
+}
+
+impl InstancingPlugin<SdfInstanceData> {
+    /// Plugin drawing an [`SdfInstanceData`] shape per instance with the built-in `sdf.wgsl`.
+    ///
+    /// Batches are expected to use a square mesh from -1 to 1 and can't be textured.
+    /// [`InstanceCulling::Gpu`] needs a culling shader adapted to the payload, see
+    /// [`InstancingPlugin::with_culling_shader`].
+    pub fn sdf() -> Self {
+        Self::default()
+            .with_shader("embedded://fundamentals/vertex_buffer/sdf.wgsl")
+            .with_instance_transform(sdf::sdf_instance_transform)
+    }
+}
+
+impl InstancingPlugin<StrokedInstanceData> {
+    /// Plugin drawing meshes with a [stroke](crate::Shape2d::with_stroke) whose color and width
+    /// are set per instance by [`StrokedInstanceData`], with the built-in `stroke.wgsl`.
+    ///
+    /// Batches can't be textured.
+    pub fn strokes() -> Self {
+        Self::default()
+            .with_shader("embedded://fundamentals/vertex_buffer/stroke.wgsl")
+            .with_instance_transform(stroke::stroked_instance_transform)
+            .with_stroke_width(stroke::stroke_width)
+            .with_culling_shader(gpu_culling::builtin_culling_shader())
+    }
+}
+
+impl<S, C> InstancingPlugin<S, C> {
+    /// Uses the shader at `path` instead of the built-in one.
+    ///
+    /// Mesh positions are at location 0, UVs at 1, normals at 2 and colors at 3, each only when
+    /// the mesh has them, followed by the attributes of `S` and then those of `C` from location 4.
+    /// Positions are a `vec3f` when `VERTEX_POSITIONS_3D` is defined, and the other mesh
+    /// attributes come with `VERTEX_UVS`, `VERTEX_NORMALS` and `VERTEX_COLORS`.
+    ///
+    /// [Stroke offsets](ATTRIBUTE_STROKE_OFFSET) come last, with `VERTEX_STROKES`, at the location
+    /// given by the `STROKE_OFFSET_LOCATION` def.
+    ///
+    /// Batches drawn together each have their own transform and [`InstanceUniformData`], which the
+    /// vertex shader looks up with `instance_batch(instance_index)` from
+    /// `fundamentals::instance_batch`.
+    pub fn with_shader(mut self, path: impl Into<AssetPath<'static>>) -> Self {
+        self.shader = Some(path.into());
+        self
+    }
+
+    /// Chooses how instance data reaches the vertex shader, [`InstancingMode::VertexBuffer`] by
+    /// default.
+    ///
+    /// [`InstancingMode::StorageBuffer`] falls back to [`InstancingMode::VertexBuffer`] when the
+    /// device allows fewer than 2 storage buffers per shader stage.
+    pub fn with_mode(mut self, mode: InstancingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Tells the plugin how the shader places an instance within its batch.
+    ///
+    /// Batches then get an `Aabb` covering their instances so Bevy can cull them, and
+    /// [`InstanceCulling`] becomes available. Without it, batches are never culled.
+    pub fn with_instance_transform(mut self, transform: fn(&S, &C) -> Affine2) -> Self {
+        self.instance_transform = Some(transform);
+        self
+    }
+
+    /// Tells the plugin how wide the stroke of an instance is, in mesh units before the
+    /// instance transform, so culling keeps instances whose stroke alone is in view.
+    ///
+    /// Only used together with [`Self::with_instance_transform`].
+    pub fn with_stroke_width(mut self, stroke_width: fn(&S, &C) -> f32) -> Self {
+        self.stroke_width = Some(stroke_width);
+        self
+    }
 
-impl Plugin for InstancingPlugin {
+    /// Uses the compute shader at `path` for [`InstanceCulling::Gpu`], which is otherwise only
+    /// available for the built-in payloads.
+    ///
+    /// The built-in `culling.wgsl` is a starting point, only its `instance_transform` function
+    /// depends on the payload layout.
+    pub fn with_culling_shader(mut self, path: impl Into<AssetPath<'static>>) -> Self {
+        self.culling_shader = Some(path.into());
+        self
+    }
+
+    /// Runs the compute shader at `path` on every batch each frame, before culling and drawing,
+    /// to update the changing data in place on the GPU. The static data is left untouched.
+    ///
+    /// The shader imports its bindings from `fundamentals::instance_simulation`, including the
+    /// time and delta time, and defines `@compute @workgroup_size(64) fn update`, which is run
+    /// once per instance.
+    ///
+    /// Any change to a batch uploads its changing data again, replacing the simulated state, so
+    /// simulated batches are best left unchanged on the CPU. This also applies to
+    /// [`InstanceCulling::Cpu`] and [layers](InstanceMaterialData::with_layers), which upload the
+    /// instances in a new order, and to the batch `Aabb`, which is computed from the CPU data.
+    pub fn with_simulation_shader(mut self, path: impl Into<AssetPath<'static>>) -> Self {
+        self.simulation_shader = Some(path.into());
+        self
+    }
+}
+
+/// How per-instance data reaches the vertex shader.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum InstancingMode {
+    /// Instance data is bound as instance-rate vertex buffers, one attribute per field.
+    #[default]
+    VertexBuffer,
+    /// Instance data is bound as two read-only storage buffers at group 3 and indexed with
+    /// `@builtin(instance_index)`. The shader is compiled with `STORAGE_INSTANCING` and the
+    /// `STATIC_INSTANCE_STRIDE`/`CHANGING_INSTANCE_STRIDE` defs (in `f32`s).
+    ///
+    /// Falls back to [`InstancingMode::VertexBuffer`] where storage buffers are unavailable,
+    /// e.g. on WebGL2.
+    StorageBuffer,
+}
+
+impl<S: InstancePayload, C: InstancePayload> Plugin for InstancingPlugin<S, C> {
     fn build(&self, app: &mut App) {
+        let uses_builtin_payloads = TypeId::of::<S>() == TypeId::of::<StaticInstanceData>()
+            && (TypeId::of::<C>() == TypeId::of::<ChangingInstanceData>()
+                || transform::is_affine::<C>());
+        assert!(
+            self.shader.is_some() || uses_builtin_payloads,
+            "InstancingPlugin<{}, {}> needs a shader, see `InstancingPlugin::with_shader`",
+            std::any::type_name::<S>(),
+            std::any::type_name::<C>(),
+        );
+
+        if !app.is_plugin_added::<instance_material::SharedMaterialPlugin>() {
+            embedded_asset!(app, "instancing.wgsl");
+            embedded_asset!(app, "arc.wgsl");
+            embedded_asset!(app, "sdf.wgsl");
+            embedded_asset!(app, "stroke.wgsl");
+            embedded_asset!(app, "culling.wgsl");
+            load_shader_library!(app, "instance_batch.wgsl");
+            load_shader_library!(app, "instance_simulation.wgsl");
+            app.add_plugins(instance_material::SharedMaterialPlugin);
+        }
+
         app.add_plugins((
-            SyncComponentPlugin::<InstanceMaterialData>::default(),
-            instance_material::CustomMaterialPlugin,
+            SyncComponentPlugin::<InstanceMaterialData<S, C>>::default(),
+            instance_material::CustomMaterialPlugin::<S, C> {
+                shader: self.shader.clone(),
+                culling_shader: self.culling_shader.clone(),
+                simulation_shader: self.simulation_shader.clone(),
+                mode: self.mode,
+                marker: PhantomData,
+            },
         ));
-        app.add_systems(Startup, setup);
+
+        if let Some(transform) = self.instance_transform {
+            let transform = culling::InstanceTransform::<S, C> {
+                transform,
+                stroke_width: self.stroke_width,
+            };
+            app.insert_resource(transform.clone());
+            app.add_systems(
+                PostUpdate,
+                culling::update_batch_aabbs::<S, C>
+                    .in_set(VisibilitySystems::CalculateBounds)
+                    .after(calculate_bounds_2d),
+            );
+
+            let render_app = app.sub_app_mut(RenderApp);
+            render_app.insert_resource(transform);
+            render_app.add_systems(ExtractSchedule, culling::extract_instance_culling::<S, C>);
+        }
 
         let render_app = app.sub_app_mut(RenderApp);
-        render_app.init_resource::<RenderCustomMesh2dInstances>();
-        render_app.add_systems(ExtractSchedule, extract_custom_mesh2d.after(extract_mesh2d));
+        render_app.init_resource::<RenderCustomMesh2dInstances<S, C>>();
+        render_app.init_resource::<ExtractedInstanceBytes<S, C>>();
+        render_app.init_resource::<culling::RenderInstanceCulling<S, C>>();
+        render_app.add_systems(
+            ExtractSchedule,
+            (
+                extract_custom_mesh2d::<S, C>,
+                remove_stale_instance_data::<S, C>,
+            )
+                .chain()
+                .after(extract_mesh2d),
+        );
     }
 }
 
-#[derive(Resource, Deref, DerefMut, Default)]
-struct RenderCustomMesh2dInstances(MainEntityHashMap<RenderMesh2dInstance>);
+#[derive(Resource, Deref, DerefMut)]
+struct RenderCustomMesh2dInstances<S, C> {
+    #[deref]
+    instances: MainEntityHashMap<RenderMesh2dInstance>,
+    marker: PhantomData<fn() -> (S, C)>,
+}
 
-fn setup(
-    mut commands: Commands,
-    window: Single<&Window, With<PrimaryWindow>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-) {
-    let aspect = window.width() / window.height();
-
-    let mut mesh = Mesh::new(
-        bevy::mesh::PrimitiveTopology::TriangleList,
-        RenderAssetUsages::all(),
-    );
-
-    mesh.insert_attribute(
-        ATTRIBUTE_CUSTOM_POSITION,
-        create_circle_vertices(0.5, 24, 0.25, 0.0, std::f32::consts::PI * 2.0),
-    );
-
-    let mesh_handle = meshes.add(mesh);
-
-    let mut rand = rand_chacha::ChaCha8Rng::from_os_rng();
-    // Spawning 1 entity
-    commands.spawn((
-        Mesh2d(mesh_handle),
-        Transform::default(),
-        Visibility::default(),
-        InstanceMaterialData {
-            static_data: (1..=100)
-                .flat_map(|x| (1..10).map(move |y| (x as f32 / 10.0, y as f32 / 10.)))
-                .map(|(x, y)| StaticInstanceData {
-                    offset: Vec2::new(
-                        rand.random_range(-1_f32..1.0),
-                        rand.random_range(-1_f32..1.0),
-                    ),
-                    color: LinearRgba::from(Color::hsla(x * 360., y, 0.5, 1.0)).to_f32_array(),
-                })
-                .collect(),
-            changing_data: (1..=100)
-                .flat_map(|x| (1..10).map(move |y| (x as f32 / 10.0, y as f32 / 10.)))
-                .map(|(x, y)| ChangingInstanceData {
-                    scale: Vec2::splat(rand.random_range(0.25..1_f32)) / aspect,
-                })
-                .collect(),
-        },
-        NoFrustumCulling,
-    ));
+impl<S, C> Default for RenderCustomMesh2dInstances<S, C> {
+    fn default() -> Self {
+        Self {
+            instances: default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// A batch of instances drawn with the entity's `Mesh2d` in a single draw call.
+///
+/// `static_data` and `changing_data` are expected to have the same length. Instances are drawn in
+/// order, unless the batch has [layers](InstanceMaterialData::with_layers).
+///
+/// Up to 16 batches with the same mesh, texture or material and [`InstanceBlendMode`] share their
+/// buffers and draw call, one batch after the other. Each keeps its own transform and
+/// [`InstanceUniformData`]. Blended batches only merge at the same depth. Batches culled with [`InstanceCulling::Gpu`] or simulated on the GPU are always drawn on their own.
+#[derive(Clone, Component)]
+#[require(InstanceUniformData)]
+pub struct InstanceMaterialData<
+    S: InstancePayload = StaticInstanceData,
+    C: InstancePayload = ChangingInstanceData,
+> {
+    static_data: Vec<S>,
+    changing_data: Vec<C>,
+    /// Renewed on every mutable access to `static_data`, so the render world knows when the
+    /// static buffer has to be uploaded again. Unique across batches, so replacing a batch with a
+    /// new one is noticed too.
+    static_revision: u32,
+    layers: Vec<f32>,
+}
 
-    commands.insert_resource(InstanceUniformData { instance: 0 });
+/// Source of [`InstanceMaterialData`] static revisions.
+static NEXT_STATIC_REVISION: AtomicU32 = AtomicU32::new(1);
 
-    commands.spawn((
-        Camera2d,
-        // Transform::from_xyz(0.0, 0.0, 15.0).looking_at(Vec3::ZERO, Vec3::Y),
-    ));
+fn next_static_revision() -> u32 {
+    NEXT_STATIC_REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
-// TO-DO:
-// - Split into two structs: StaticData vs ChangingData
-#[derive(Clone, Component)]
-struct InstanceMaterialData {
-    static_data: Vec<StaticInstanceData>,
-    changing_data: Vec<ChangingInstanceData>,
+impl<S: InstancePayload, C: InstancePayload> InstanceMaterialData<S, C> {
+    pub fn new(static_data: Vec<S>, changing_data: Vec<C>) -> Self {
+        debug_assert_eq!(static_data.len(), changing_data.len());
+        Self {
+            static_data,
+            changing_data,
+            static_revision: next_static_revision(),
+            layers: Vec::new(),
+        }
+    }
+
+    /// Sorts the instances by `layers` before drawing, so instances on higher layers are drawn
+    /// on top of those below them. Instances without a layer are on layer 0.
+    ///
+    /// Sorting happens on the CPU whenever the batch changes, and requires the static data to be
+    /// uploaded again when the order changes.
+    pub fn with_layers(mut self, layers: Vec<f32>) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Number of instances in the batch.
+    pub fn len(&self) -> usize {
+        self.static_data.len().min(self.changing_data.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn static_data(&self) -> &[S] {
+        &self.static_data
+    }
+
+    /// Marks the static data as modified, which causes a re-upload of the whole static buffer.
+    pub fn static_data_mut(&mut self) -> &mut Vec<S> {
+        self.static_revision = next_static_revision();
+        &mut self.static_data
+    }
+
+    pub fn changing_data(&self) -> &[C] {
+        &self.changing_data
+    }
+
+    pub fn changing_data_mut(&mut self) -> &mut Vec<C> {
+        &mut self.changing_data
+    }
+
+    pub fn layers(&self) -> &[f32] {
+        &self.layers
+    }
+
+    /// Leaving the layers empty disables sorting.
+    pub fn layers_mut(&mut self) -> &mut Vec<f32> {
+        &mut self.layers
+    }
 }
 
-#[derive(Debug, Clone, Resource, Reflect, ExtractResource, ShaderType)]
-struct InstanceUniformData {
-    instance: u32,
+/// Batch-wide values of an [`InstanceMaterialData`], for effects that would otherwise require
+/// touching every instance.
+///
+/// Every batch has one, which can be updated every frame without uploading any instance data.
+/// The shader reads the tint and `params`, along with the time, from the batch uniform at
+/// group 1, see `fundamentals::instance_batch`.
+#[derive(Debug, Clone, Component, Reflect, ExtractComponent)]
+pub struct InstanceUniformData {
+    /// Multiplies the color of every instance.
+    pub tint: LinearRgba,
+    /// Scales the instances and their offsets about the batch origin.
+    pub scale: Vec2,
+    /// Moves the instances after `scale`.
+    pub offset: Vec2,
+    /// Free for custom shaders, unused by the built-in one.
+    pub params: Vec4,
 }
 
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-#[repr(C)]
-struct StaticInstanceData {
-    color: [f32; 4],
-    offset: Vec2,
+impl Default for InstanceUniformData {
+    fn default() -> Self {
+        Self {
+            tint: LinearRgba::WHITE,
+            scale: Vec2::ONE,
+            offset: Vec2::ZERO,
+            params: Vec4::ZERO,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-#[repr(C)]
-struct ChangingInstanceData {
-    scale: Vec2,
+impl InstanceUniformData {
+    /// Transform of the instances within the entity's local space.
+    pub fn affine(&self) -> Affine2 {
+        Affine2::from_translation(self.offset) * Affine2::from_scale(self.scale)
+    }
 }
 
-/// Default values:
+/// Texture sampled by every instance of the batch, typically an atlas.
 ///
-/// radius: 1.0,
-///
-/// num_subdivisions: 24,
-///
-/// inner_radius: 0.0,
-///
-/// start_angle: 0.0,
-///   
-/// end_angle: std::f32::consts::PI * 2,
-fn create_circle_vertices(
-    radius: f32,
-    num_subdivisions: usize,
-    inner_radius: f32,
-    start_angle: f32,
-    end_angle: f32,
-) -> Vec<[f32; 2]> {
-    let num_vertices = num_subdivisions * 3 * 2;
-    let mut vertex_data = Vec::with_capacity(num_vertices);
-
-    let mut add_vertex = |x, y| {
-        // Bevy requires data to be given as array
-        vertex_data.push([x, y]);
-    };
+/// Instances pick their region with [`StaticInstanceData::uv_rect`]. The mesh must have
+/// `Mesh::ATTRIBUTE_UV_0`.
+#[derive(Debug, Clone, Component, ExtractComponent)]
+pub struct InstanceTexture(pub Handle<Image>);
 
-    for i in 0..num_subdivisions {
-        let angle_1 =
-            start_angle + (i as f32 + 0.0) * (end_angle - start_angle) / num_subdivisions as f32;
-        let angle_2 =
-            start_angle + (i as f32 + 1.0) * (end_angle - start_angle) / num_subdivisions as f32;
+/// How the instances of a batch are blended with what is behind them.
+///
+/// Batches without this component use [`InstanceBlendMode::Alpha`]. Opaque and masked batches
+/// are drawn in the `Opaque2d` and `AlphaMask2d` phases, which rely on the depth buffer instead
+/// of sorting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Component, ExtractComponent)]
+pub enum InstanceBlendMode {
+    /// Overwrites the target and writes depth.
+    Opaque,
+    /// Like [`InstanceBlendMode::Opaque`], but discards fragments with an alpha below 0.5.
+    Mask,
+    /// Straight alpha blending.
+    #[default]
+    Alpha,
+    /// Blending for colors already multiplied by their alpha.
+    Premultiplied,
+    /// Adds the color, scaled by its alpha, to the target.
+    Additive,
+    /// Multiplies the target by the color, faded towards white by its alpha.
+    Multiply,
+}
 
-        let c1 = angle_1.cos();
-        let s1 = angle_1.sin();
-        let c2 = angle_2.cos();
-        let s2 = angle_2.sin();
+/// Per-instance data that is rarely updated.
+#[derive(
+    Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, fundamentals_macros::InstanceAttributes,
+)]
+#[repr(C)]
+pub struct StaticInstanceData {
+    pub color: [f32; 4],
+    pub offset: Vec2,
+    /// Region of the [`InstanceTexture`] mapped onto the mesh UVs, as `[min_x, min_y, max_x,
+    /// max_y]` in normalized texture coordinates.
+    pub uv_rect: [f32; 4],
+}
 
-        // First triangle
-        add_vertex(c1 * radius, s1 * radius);
-        add_vertex(c2 * radius, s2 * radius);
-        add_vertex(c1 * inner_radius, s1 * inner_radius);
+impl Default for StaticInstanceData {
+    fn default() -> Self {
+        Self {
+            color: [1.0; 4],
+            offset: Vec2::ZERO,
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+        }
+    }
+}
 
-        // Second triangle
-        add_vertex(c1 * inner_radius, s1 * inner_radius);
-        add_vertex(c2 * radius, s2 * radius);
-        add_vertex(c2 * inner_radius, s2 * inner_radius);
+impl StaticInstanceData {
+    /// UV rect of the texture at `index` in an atlas `layout`.
+    pub fn atlas_uv_rect(layout: &TextureAtlasLayout, index: usize) -> [f32; 4] {
+        let rect = layout.textures[index].as_rect();
+        let size = layout.size.as_vec2();
+        [
+            rect.min.x / size.x,
+            rect.min.y / size.y,
+            rect.max.x / size.x,
+            rect.max.y / size.y,
+        ]
     }
+}
+
+/// Per-instance data that is expected to be updated often.
+#[derive(
+    Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, fundamentals_macros::InstanceAttributes,
+)]
+#[repr(C)]
+pub struct ChangingInstanceData {
+    /// Scale of the instance, applied to the mesh before `offset`. See [`AffineInstanceData`] to
+    /// rotate instances too.
+    pub scale: Vec2,
+}
+
+/// Number of bytes of instance data copied into the render world during the last extraction.
+#[derive(Resource)]
+struct ExtractedInstanceBytes<S, C>(usize, PhantomData<fn() -> (S, C)>);
 
-    vertex_data
+impl<S, C> Default for ExtractedInstanceBytes<S, C> {
+    fn default() -> Self {
+        Self(0, PhantomData)
+    }
 }
 
-fn extract_custom_mesh2d(
+fn extract_custom_mesh2d<S: InstancePayload, C: InstancePayload>(
     mut commands: Commands,
-    mut previous_len: Local<usize>,
     query: Extract<
         Query<(
             Entity,
             RenderEntity,
             &GlobalTransform,
             &Mesh2d,
-            &InstanceMaterialData,
+            Ref<InstanceMaterialData<S, C>>,
+            &InstanceUniformData,
         )>,
     >,
-    mut render_mesh_instances: ResMut<RenderCustomMesh2dInstances>,
+    mut extracted_query: Query<&mut InstanceMaterialData<S, C>>,
+    mut render_mesh_instances: ResMut<RenderCustomMesh2dInstances<S, C>>,
+    mut extracted_bytes: ResMut<ExtractedInstanceBytes<S, C>>,
 ) {
-    let mut values = Vec::with_capacity(*previous_len);
-    for (entity, render_entity, transform, handle, instance_material_data) in &query {
+    extracted_bytes.0 = 0;
+    // Rebuilt every frame so despawned entities and entities that lost their `Mesh2d` or
+    // `InstanceMaterialData` don't linger.
+    render_mesh_instances.clear();
+    for (entity, render_entity, transform, handle, instance_material_data, uniform) in &query {
+        // The uniform's scale and offset are folded into the batch transform, so culling sees
+        // them too.
+        let world_from_local = transform.affine()
+            * Affine3A::from_translation(uniform.offset.extend(0.0))
+            * Affine3A::from_scale(uniform.scale.extend(1.0));
         let transforms = Mesh2dTransforms {
-            world_from_local: (&transform.affine()).into(),
+            world_from_local: (&world_from_local).into(),
             flags: MeshFlags::empty().bits(),
         };
-        values.push((render_entity, instance_material_data.clone()));
+
+        // The render entity may have been recreated, in which case everything has to be sent again.
+        match extracted_query.get_mut(render_entity) {
+            Ok(_) if !instance_material_data.is_changed() => {}
+            Ok(mut extracted) => {
+                if extracted.static_revision != instance_material_data.static_revision {
+                    extracted
+                        .static_data
+                        .clone_from(&instance_material_data.static_data);
+                    extracted.static_revision = instance_material_data.static_revision;
+                    extracted_bytes.0 += size_of_val(instance_material_data.static_data());
+                }
+                extracted
+                    .changing_data
+                    .clone_from(&instance_material_data.changing_data);
+                extracted.layers.clone_from(&instance_material_data.layers);
+                extracted_bytes.0 += size_of_val(instance_material_data.changing_data())
+                    + size_of_val(instance_material_data.layers());
+            }
+            Err(_) => {
+                extracted_bytes.0 += size_of_val(instance_material_data.static_data())
+                    + size_of_val(instance_material_data.changing_data())
+                    + size_of_val(instance_material_data.layers());
+                commands
+                    .entity(render_entity)
+                    .try_insert(instance_material_data.clone());
+            }
+        }
+
         render_mesh_instances.insert(
             entity.into(),
             RenderMesh2dInstance {
@@ -207,6 +600,622 @@ fn extract_custom_mesh2d(
             },
         );
     }
-    *previous_len = values.len();
-    commands.try_insert_batch(values);
+}
+
+/// Removes the extracted [`InstanceMaterialData`] of render entities whose main entity is no longer
+/// instanced, which in turn releases their GPU buffers.
+fn remove_stale_instance_data<S: InstancePayload, C: InstancePayload>(
+    mut commands: Commands,
+    query: Query<(Entity, &MainEntity), With<InstanceMaterialData<S, C>>>,
+    render_mesh_instances: Res<RenderCustomMesh2dInstances<S, C>>,
+) {
+    for (entity, main_entity) in &query {
+        if !render_mesh_instances.contains_key(main_entity) {
+            commands
+                .entity(entity)
+                .remove::<InstanceMaterialData<S, C>>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy::{
+        ecs::schedule::Schedule,
+        render::{
+            MainWorld,
+            render_asset::RenderAssets,
+            render_resource::{
+                Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+            },
+            renderer::{RenderDevice, RenderQueue, WgpuWrapper},
+            texture::{FallbackImage, GpuImage},
+            view::ViewUniforms,
+        },
+        sprite_render::RenderMesh2dInstances,
+    };
+
+    use super::{
+        culling::RenderInstanceCulling,
+        instance_material::{
+            Custom2dPipeline, InstanceBuffer, InstanceData, InstanceTextureBindGroup,
+            prepare_batch_uniforms, prepare_instance_buffers, prepare_texture_bind_groups,
+            queued_mesh, release_instance_buffers,
+        },
+        material::RenderInstancedMaterials,
+        merging::{InstanceMergeGroups, group_batches},
+        *,
+    };
+
+    type TestMesh2dInstances =
+        RenderCustomMesh2dInstances<StaticInstanceData, ChangingInstanceData>;
+    type TestExtractedBytes = ExtractedInstanceBytes<StaticInstanceData, ChangingInstanceData>;
+
+    struct TestWorlds {
+        render_world: World,
+        schedule: Schedule,
+    }
+
+    impl TestWorlds {
+        fn new() -> Self {
+            let mut render_world = World::new();
+            render_world.insert_resource(MainWorld::default());
+            render_world.init_resource::<TestMesh2dInstances>();
+            render_world.init_resource::<TestExtractedBytes>();
+
+            let mut schedule = Schedule::default();
+            schedule.add_systems(
+                (
+                    extract_custom_mesh2d::<StaticInstanceData, ChangingInstanceData>,
+                    remove_stale_instance_data::<StaticInstanceData, ChangingInstanceData>,
+                )
+                    .chain(),
+            );
+
+            TestWorlds {
+                render_world,
+                schedule,
+            }
+        }
+
+        /// Also merges the batches and prepares and releases the instance buffers and the batch
+        /// uniforms after each extraction, on a stub device.
+        fn with_instance_buffers() -> Self {
+            let mut worlds = Self::new();
+            let (device, queue) = wgpu::Device::noop(&default());
+            let render_world = &mut worlds.render_world;
+            render_world.insert_resource(RenderDevice::from(device));
+            render_world.insert_resource(RenderQueue(Arc::new(WgpuWrapper::new(queue))));
+            render_world.init_resource::<ViewUniforms>();
+            render_world.init_resource::<Time>();
+            render_world
+                .init_resource::<InstanceBuffer<StaticInstanceData, ChangingInstanceData>>();
+            render_world
+                .init_resource::<InstanceMergeGroups<StaticInstanceData, ChangingInstanceData>>();
+            render_world
+                .init_resource::<RenderInstanceCulling<StaticInstanceData, ChangingInstanceData>>();
+            render_world.init_resource::<RenderInstancedMaterials<StaticInstanceData, ChangingInstanceData>>();
+            let pipeline = Custom2dPipeline::<StaticInstanceData, ChangingInstanceData>::new(
+                render_world,
+                Some(Handle::default()),
+                InstancingMode::VertexBuffer,
+                false,
+            );
+            render_world.insert_resource(pipeline);
+
+            worlds.schedule.add_systems(
+                (
+                    group_batches::<StaticInstanceData, ChangingInstanceData>,
+                    release_instance_buffers::<StaticInstanceData, ChangingInstanceData>,
+                    prepare_instance_buffers::<StaticInstanceData, ChangingInstanceData>,
+                    prepare_batch_uniforms::<StaticInstanceData, ChangingInstanceData>,
+                )
+                    .chain()
+                    .after(remove_stale_instance_data::<StaticInstanceData, ChangingInstanceData>),
+            );
+            worlds
+        }
+
+        fn main_world(&mut self) -> Mut<'_, MainWorld> {
+            self.render_world.resource_mut::<MainWorld>()
+        }
+
+        fn spawn_batch(&mut self, len: usize) -> Entity {
+            let entity = self
+                .main_world()
+                .spawn((
+                    GlobalTransform::default(),
+                    Mesh2d(Handle::default()),
+                    test_batch(len),
+                ))
+                .id();
+            self.respawn_render_entity(entity);
+            entity
+        }
+
+        /// Mirrors what entity syncing does when a synced component is removed.
+        fn respawn_render_entity(&mut self, entity: Entity) {
+            if let Some(render_entity) = self.main_world().get::<RenderEntity>(entity) {
+                let render_entity = render_entity.id();
+                self.render_world.despawn(render_entity);
+            }
+            let render_entity = self.render_world.spawn(MainEntity::from(entity)).id();
+            self.main_world()
+                .entity_mut(entity)
+                .insert(RenderEntity::from(render_entity));
+        }
+
+        /// Mirrors what entity syncing does when a main world entity is despawned.
+        fn despawn(&mut self, entity: Entity) {
+            let render_entity = self.main_world().get::<RenderEntity>(entity).unwrap().id();
+            self.main_world().despawn(entity);
+            self.render_world.despawn(render_entity);
+        }
+
+        fn extracted_batch_count(&mut self) -> usize {
+            self.render_world
+                .query::<&InstanceMaterialData>()
+                .iter(&self.render_world)
+                .count()
+        }
+
+        fn instance_buffer_count(&mut self) -> usize {
+            self.render_world
+                .query::<&InstanceData<StaticInstanceData, ChangingInstanceData>>()
+                .iter(&self.render_world)
+                .count()
+        }
+
+        /// Number of instances in all the instance buffers, which merged batches share.
+        fn uploaded_instance_count(&mut self) -> usize {
+            self.render_world
+                .query::<&InstanceData<StaticInstanceData, ChangingInstanceData>>()
+                .iter(&self.render_world)
+                .map(|instance_data| instance_data.length)
+                .sum()
+        }
+
+        /// Runs one extraction and returns the number of bytes it copied.
+        fn extract(&mut self) -> usize {
+            self.schedule.run(&mut self.render_world);
+            self.main_world().increment_change_tick();
+            self.render_world.resource::<TestExtractedBytes>().0
+        }
+    }
+
+    fn test_batch(len: usize) -> InstanceMaterialData {
+        InstanceMaterialData::new(
+            vec![StaticInstanceData::default(); len],
+            vec![ChangingInstanceData { scale: Vec2::ONE }; len],
+        )
+    }
+
+    #[test]
+    fn extraction_only_copies_changed_data() {
+        const LEN: usize = 1000;
+        let static_bytes = LEN * size_of::<StaticInstanceData>();
+        let changing_bytes = LEN * size_of::<ChangingInstanceData>();
+
+        let mut worlds = TestWorlds::new();
+        let entity = worlds.spawn_batch(LEN);
+
+        assert_eq!(worlds.extract(), static_bytes + changing_bytes);
+        assert_eq!(worlds.extract(), 0);
+        assert_eq!(worlds.extract(), 0);
+
+        worlds
+            .main_world()
+            .get_mut::<InstanceMaterialData>(entity)
+            .unwrap()
+            .changing_data_mut()[0]
+            .scale = Vec2::splat(2.0);
+        assert_eq!(worlds.extract(), changing_bytes);
+        assert_eq!(worlds.extract(), 0);
+
+        worlds
+            .main_world()
+            .get_mut::<InstanceMaterialData>(entity)
+            .unwrap()
+            .static_data_mut()[0]
+            .offset = Vec2::ONE;
+        assert_eq!(worlds.extract(), static_bytes + changing_bytes);
+        assert_eq!(worlds.extract(), 0);
+
+        let render_entity = worlds
+            .main_world()
+            .get::<RenderEntity>(entity)
+            .unwrap()
+            .id();
+        let extracted = worlds
+            .render_world
+            .get::<InstanceMaterialData>(render_entity)
+            .unwrap();
+        assert_eq!(extracted.static_data()[0].offset, Vec2::ONE);
+        assert_eq!(extracted.changing_data()[0].scale, Vec2::splat(2.0));
+    }
+
+    #[test]
+    fn replaced_batches_are_extracted_again() {
+        let mut worlds = TestWorlds::with_instance_buffers();
+        let entity = worlds.spawn_batch(10);
+        worlds.extract();
+
+        let replacement = test_batch(20);
+        let bytes = 20 * (size_of::<StaticInstanceData>() + size_of::<ChangingInstanceData>());
+        worlds.main_world().entity_mut(entity).insert(replacement);
+        assert_eq!(worlds.extract(), bytes);
+
+        let render_entity = worlds
+            .main_world()
+            .get::<RenderEntity>(entity)
+            .unwrap()
+            .id();
+        let extracted = worlds
+            .render_world
+            .get::<InstanceMaterialData>(render_entity)
+            .unwrap();
+        assert_eq!(extracted.static_data().len(), 20);
+        assert_eq!(extracted.len(), 20);
+        assert_eq!(worlds.uploaded_instance_count(), 20);
+    }
+
+    #[test]
+    fn layers_are_extracted_with_the_changing_data() {
+        const LEN: usize = 100;
+        let changing_bytes = LEN * size_of::<ChangingInstanceData>();
+        let layer_bytes = LEN * size_of::<f32>();
+
+        let mut worlds = TestWorlds::new();
+        let entity = worlds.spawn_batch(LEN);
+        worlds.extract();
+
+        worlds
+            .main_world()
+            .get_mut::<InstanceMaterialData>(entity)
+            .unwrap()
+            .layers_mut()
+            .extend((0..LEN).map(|i| -(i as f32)));
+        assert_eq!(worlds.extract(), changing_bytes + layer_bytes);
+        assert_eq!(worlds.extract(), 0);
+
+        let render_entity = worlds
+            .main_world()
+            .get::<RenderEntity>(entity)
+            .unwrap()
+            .id();
+        let extracted = worlds
+            .render_world
+            .get::<InstanceMaterialData>(render_entity)
+            .unwrap();
+        assert_eq!(extracted.layers()[LEN - 1], -((LEN - 1) as f32));
+    }
+
+    #[test]
+    fn uniform_scale_and_offset_are_part_of_the_batch_transform() {
+        let mut worlds = TestWorlds::new();
+        let entity = worlds.spawn_batch(10);
+        worlds
+            .main_world()
+            .entity_mut(entity)
+            .insert(GlobalTransform::from_translation(Vec3::new(1.0, 2.0, 3.0)));
+        worlds.extract();
+
+        *worlds
+            .main_world()
+            .get_mut::<InstanceUniformData>(entity)
+            .unwrap() = InstanceUniformData {
+            scale: Vec2::splat(2.0),
+            offset: Vec2::new(10.0, 0.0),
+            ..default()
+        };
+        // Only the uniform changed, so no instance data is copied.
+        assert_eq!(worlds.extract(), 0);
+
+        let mesh_instance =
+            &worlds.render_world.resource::<TestMesh2dInstances>()[&MainEntity::from(entity)];
+        let world_from_local = Affine3A::from(&mesh_instance.transforms.world_from_local);
+        assert_eq!(
+            world_from_local.transform_point3(Vec3::new(1.0, 1.0, 0.0)),
+            Vec3::new(13.0, 4.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn render_world_state_is_released_with_the_batches() {
+        const BATCHES: usize = 4000;
+
+        let mut worlds = TestWorlds::with_instance_buffers();
+        let entities: Vec<_> = (0..BATCHES).map(|_| worlds.spawn_batch(16)).collect();
+
+        worlds.extract();
+        assert_eq!(
+            worlds.render_world.resource::<TestMesh2dInstances>().len(),
+            BATCHES
+        );
+        assert_eq!(worlds.extracted_batch_count(), BATCHES);
+        assert_eq!(worlds.uploaded_instance_count(), BATCHES * 16);
+
+        for (i, &entity) in entities.iter().enumerate() {
+            match i % 3 {
+                0 => worlds.despawn(entity),
+                1 => {
+                    worlds
+                        .main_world()
+                        .entity_mut(entity)
+                        .remove::<InstanceMaterialData>();
+                    worlds.respawn_render_entity(entity);
+                }
+                _ => {
+                    worlds.main_world().entity_mut(entity).remove::<Mesh2d>();
+                }
+            }
+        }
+
+        worlds.extract();
+        assert!(
+            worlds
+                .render_world
+                .resource::<TestMesh2dInstances>()
+                .is_empty()
+        );
+        assert_eq!(worlds.extracted_batch_count(), 0);
+        assert_eq!(worlds.instance_buffer_count(), 0);
+
+        // Re-adding a mesh brings the batch back.
+        worlds
+            .main_world()
+            .entity_mut(entities[2])
+            .insert(Mesh2d(Handle::default()));
+        worlds.extract();
+        assert_eq!(
+            worlds.render_world.resource::<TestMesh2dInstances>().len(),
+            1
+        );
+        assert_eq!(worlds.extracted_batch_count(), 1);
+        assert_eq!(worlds.instance_buffer_count(), 1);
+        assert_eq!(worlds.uploaded_instance_count(), 16);
+    }
+
+    #[test]
+    fn merged_groups_are_drawn_when_only_a_member_is_visible() {
+        let mut worlds = TestWorlds::with_instance_buffers();
+        worlds.render_world.init_resource::<RenderMesh2dInstances>();
+        worlds.schedule.add_systems(extract_mesh2d);
+
+        let batches = [worlds.spawn_batch(10), worlds.spawn_batch(20)];
+        worlds.extract();
+        let merge_groups = worlds
+            .render_world
+            .resource::<InstanceMergeGroups<StaticInstanceData, ChangingInstanceData>>();
+        let (leader_render_entity, leader) = merge_groups.leaders[&MainEntity::from(batches[0])];
+        let member = batches
+            .into_iter()
+            .find(|&batch| MainEntity::from(batch) != leader)
+            .unwrap();
+
+        // Only the member is on screen.
+        let mut visible = ViewVisibility::HIDDEN;
+        visible.set();
+        worlds
+            .main_world()
+            .entity_mut(leader.id())
+            .insert(ViewVisibility::HIDDEN);
+        worlds.main_world().entity_mut(member).insert(visible);
+        worlds.extract();
+        assert_eq!(
+            worlds
+                .render_world
+                .resource::<InstanceMergeGroups<StaticInstanceData, ChangingInstanceData>>()
+                .leaders[&MainEntity::from(member)],
+            (leader_render_entity, leader)
+        );
+
+        // Bevy only extracts the visible member, but the group is still drawn with its mesh.
+        assert!(
+            !worlds
+                .render_world
+                .resource::<RenderMesh2dInstances>()
+                .contains_key(&leader)
+        );
+        assert_eq!(
+            queued_mesh(
+                worlds.render_world.resource::<TestMesh2dInstances>(),
+                &leader
+            ),
+            Some(AssetId::default())
+        );
+        let instance_data = worlds
+            .render_world
+            .get::<InstanceData<StaticInstanceData, ChangingInstanceData>>(leader_render_entity)
+            .unwrap();
+        assert_eq!(instance_data.length, 30);
+    }
+
+    #[test]
+    fn removed_batches_leave_the_other_ranges_in_place() {
+        let mut worlds = TestWorlds::with_instance_buffers();
+        let batches = [10, 20, 30].map(|len| worlds.spawn_batch(len));
+        // Batches merge wherever they are.
+        for (i, &batch) in batches.iter().enumerate() {
+            worlds
+                .main_world()
+                .entity_mut(batch)
+                .insert(GlobalTransform::from_translation(Vec3::X * i as f32));
+        }
+        worlds.extract();
+
+        let (leader_render_entity, _) = worlds
+            .render_world
+            .resource::<InstanceMergeGroups<StaticInstanceData, ChangingInstanceData>>()
+            .leaders[&MainEntity::from(batches[0])];
+        let ranges = |worlds: &TestWorlds| {
+            worlds
+                .render_world
+                .get::<InstanceData<StaticInstanceData, ChangingInstanceData>>(leader_render_entity)
+                .unwrap()
+                .batch_ranges()
+        };
+        let before = ranges(&worlds);
+        assert_eq!(before.len(), 3);
+        assert_eq!(before[2].1, 60);
+
+        // The leader draws the first range, so the second one is neither the leader's nor last.
+        let (_, removed) = before[1].0.unwrap();
+        worlds.despawn(removed.id());
+        worlds.extract();
+
+        let after = ranges(&worlds);
+        assert_eq!(after, [before[0], (None, before[1].1), before[2]]);
+        let instance_data = worlds
+            .render_world
+            .get::<InstanceData<StaticInstanceData, ChangingInstanceData>>(leader_render_entity)
+            .unwrap();
+        assert_eq!(instance_data.length, 60);
+    }
+
+    #[test]
+    fn payload_kinds_are_extracted_separately() {
+        type Particles = InstanceMaterialData<f32, Vec2>;
+
+        let mut worlds = TestWorlds::new();
+        worlds
+            .render_world
+            .init_resource::<RenderCustomMesh2dInstances<f32, Vec2>>();
+        worlds
+            .render_world
+            .init_resource::<ExtractedInstanceBytes<f32, Vec2>>();
+        worlds.schedule.add_systems(
+            (
+                extract_custom_mesh2d::<f32, Vec2>,
+                remove_stale_instance_data::<f32, Vec2>,
+            )
+                .chain(),
+        );
+
+        worlds.spawn_batch(10);
+        let particles = worlds
+            .main_world()
+            .spawn((
+                GlobalTransform::default(),
+                Mesh2d(Handle::default()),
+                Particles::new(vec![1.0; 4], vec![Vec2::ONE; 4]),
+            ))
+            .id();
+        worlds.respawn_render_entity(particles);
+        worlds.extract();
+
+        assert_eq!(
+            worlds.render_world.resource::<TestMesh2dInstances>().len(),
+            1
+        );
+        assert_eq!(
+            worlds
+                .render_world
+                .resource::<RenderCustomMesh2dInstances<f32, Vec2>>()
+                .len(),
+            1
+        );
+        assert_eq!(
+            worlds
+                .render_world
+                .resource::<ExtractedInstanceBytes<f32, Vec2>>()
+                .0,
+            4 * (size_of::<f32>() + size_of::<Vec2>())
+        );
+        assert_eq!(worlds.extracted_batch_count(), 1);
+    }
+
+    fn test_gpu_image(render_device: &RenderDevice) -> GpuImage {
+        let size = Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let texture = render_device.create_texture(&TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        GpuImage {
+            texture_view: texture.create_view(&default()),
+            texture,
+            texture_format: TextureFormat::Rgba8UnormSrgb,
+            sampler: render_device.create_sampler(&default()),
+            size,
+            mip_level_count: 1,
+        }
+    }
+
+    #[test]
+    fn texture_bind_groups_are_kept_until_their_image_changes() {
+        let mut worlds = TestWorlds::with_instance_buffers();
+        let render_device = worlds.render_world.resource::<RenderDevice>().clone();
+        let fallback = test_gpu_image(&render_device);
+        worlds.render_world.insert_resource(FallbackImage {
+            d1: fallback.clone(),
+            d2: fallback.clone(),
+            d2_array: fallback.clone(),
+            cube: fallback.clone(),
+            cube_array: fallback.clone(),
+            d3: fallback,
+        });
+        worlds
+            .render_world
+            .init_resource::<RenderAssets<GpuImage>>();
+        worlds.schedule.add_systems(
+            prepare_texture_bind_groups::<StaticInstanceData, ChangingInstanceData>
+                .after(prepare_batch_uniforms::<StaticInstanceData, ChangingInstanceData>),
+        );
+
+        let image = Handle::<Image>::default();
+        worlds
+            .render_world
+            .resource_mut::<RenderAssets<GpuImage>>()
+            .insert(&image, test_gpu_image(&render_device));
+        let entity = worlds.spawn_batch(4);
+        let render_entity = worlds
+            .main_world()
+            .get::<RenderEntity>(entity)
+            .unwrap()
+            .id();
+        worlds
+            .render_world
+            .entity_mut(render_entity)
+            .insert(InstanceTexture(image.clone()));
+        let bind_group = |worlds: &TestWorlds| {
+            worlds
+                .render_world
+                .entity(render_entity)
+                .get_ref::<InstanceTextureBindGroup<StaticInstanceData, ChangingInstanceData>>()
+                .map(|bind_group| (bind_group.0.id(), bind_group.last_changed()))
+        };
+
+        worlds.extract();
+        let (first_id, first_tick) = bind_group(&worlds).unwrap();
+        worlds.extract();
+        assert_eq!(bind_group(&worlds), Some((first_id, first_tick)));
+
+        // A modified image is prepared again as a new `GpuImage`.
+        worlds
+            .render_world
+            .resource_mut::<RenderAssets<GpuImage>>()
+            .insert(&image, test_gpu_image(&render_device));
+        worlds.extract();
+        let (second_id, _) = bind_group(&worlds).unwrap();
+        assert_ne!(second_id, first_id);
+
+        worlds
+            .render_world
+            .resource_mut::<RenderAssets<GpuImage>>()
+            .remove(&image);
+        worlds.extract();
+        assert_eq!(bind_group(&worlds), None);
+    }
 }
@@ -0,0 +1,148 @@
+use bevy::{math::Affine2, prelude::*};
+
+use super::ChangingInstanceData;
+
+// Mirror the shape kinds of `sdf.wgsl`.
+const CIRCLE: u32 = 0;
+const RING: u32 = 1;
+const ROUNDED_BOX: u32 = 2;
+const SEGMENT: u32 = 3;
+
+/// Static payload of [`InstancingPlugin::sdf`](super::InstancingPlugin::sdf), a shape drawn from
+/// its signed distance function per instance, with smooth edges at any scale.
+///
+/// The batch's mesh is a square from -1 to 1, like
+/// [`Shape2d::rectangle(Vec2::ONE)`](crate::Shape2d::rectangle), which the vertex shader fits
+/// around each instance's shape and outline.
+#[derive(
+    Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, fundamentals_macros::InstanceAttributes,
+)]
+#[repr(C)]
+pub struct SdfInstanceData {
+    pub color: [f32; 4],
+    pub offset: Vec2,
+    shape: u32,
+    /// Width of the outline drawn around the shape, 0 for none.
+    pub outline_width: f32,
+    /// Shape dependent, see the constructors.
+    params: [f32; 4],
+    pub outline_color: [f32; 4],
+}
+
+impl Default for SdfInstanceData {
+    /// A white circle of radius 1.
+    fn default() -> Self {
+        Self::circle(1.0)
+    }
+}
+
+impl SdfInstanceData {
+    fn new(shape: u32, params: [f32; 4]) -> Self {
+        Self {
+            color: [1.0; 4],
+            offset: Vec2::ZERO,
+            shape,
+            outline_width: 0.0,
+            params,
+            outline_color: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    pub fn circle(radius: f32) -> Self {
+        Self::new(CIRCLE, [radius, 0.0, 0.0, 0.0])
+    }
+
+    /// Ring of `thickness` inside a circle of `radius`.
+    pub fn ring(radius: f32, thickness: f32) -> Self {
+        Self::new(RING, [radius, thickness, 0.0, 0.0])
+    }
+
+    /// Box of `half_size` whose corners are rounded by `corner_radius`.
+    pub fn rounded_box(half_size: Vec2, corner_radius: f32) -> Self {
+        let corner_radius = corner_radius.clamp(0.0, half_size.min_element());
+        Self::new(ROUNDED_BOX, [half_size.x, half_size.y, corner_radius, 0.0])
+    }
+
+    /// Line of `thickness` from `start` to `end`, with round caps. Sets the `offset` to its
+    /// center.
+    pub fn segment(start: Vec2, end: Vec2, thickness: f32) -> Self {
+        let half_length = (end - start) / 2.0;
+        Self {
+            offset: start + half_length,
+            ..Self::new(SEGMENT, [half_length.x, half_length.y, thickness, 0.0])
+        }
+    }
+
+    pub fn with_color(mut self, color: impl Into<LinearRgba>) -> Self {
+        self.color = color.into().to_f32_array();
+        self
+    }
+
+    pub fn with_offset(mut self, offset: Vec2) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Draws an outline of `width` around the shape, outside of it.
+    pub fn with_outline(mut self, width: f32, color: impl Into<LinearRgba>) -> Self {
+        self.outline_width = width;
+        self.outline_color = color.into().to_f32_array();
+        self
+    }
+
+    /// Half size of the box around the shape and its outline, centered on the `offset`.
+    ///
+    /// Mirrors `half_extents` in `sdf.wgsl`.
+    fn half_extents(&self) -> Vec2 {
+        let [x, y, z, _] = self.params;
+        let shape = match self.shape {
+            CIRCLE | RING => Vec2::splat(x),
+            ROUNDED_BOX => Vec2::new(x, y),
+            _ => Vec2::new(x, y).abs() + z / 2.0,
+        };
+        shape + self.outline_width
+    }
+}
+
+/// Maps the square mesh onto the box around each shape, see [`SdfInstanceData::half_extents`].
+pub(super) fn sdf_instance_transform(
+    static_data: &SdfInstanceData,
+    changing_data: &ChangingInstanceData,
+) -> Affine2 {
+    Affine2::from_scale_angle_translation(
+        changing_data.scale * static_data.half_extents(),
+        0.0,
+        static_data.offset,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes_are_bounded_with_their_outline() {
+        let circle = SdfInstanceData::circle(2.0).with_outline(0.5, Color::BLACK);
+        assert_eq!(circle.half_extents(), Vec2::splat(2.5));
+        assert_eq!(
+            SdfInstanceData::ring(2.0, 0.5).half_extents(),
+            Vec2::splat(2.0)
+        );
+        assert_eq!(
+            SdfInstanceData::rounded_box(Vec2::new(3.0, 1.0), 5.0).params,
+            [3.0, 1.0, 1.0, 0.0]
+        );
+
+        let segment = SdfInstanceData::segment(Vec2::new(1.0, 1.0), Vec2::new(5.0, -1.0), 1.0);
+        assert_eq!(segment.offset, Vec2::new(3.0, 0.0));
+        assert_eq!(segment.half_extents(), Vec2::new(2.5, 1.5));
+
+        let transform =
+            sdf_instance_transform(&segment, &ChangingInstanceData { scale: Vec2::ONE });
+        assert_eq!(
+            transform.transform_point2(Vec2::NEG_ONE),
+            Vec2::new(0.5, -1.5)
+        );
+        assert_eq!(transform.transform_point2(Vec2::ONE), Vec2::new(5.5, 1.5));
+    }
+}
@@ -0,0 +1,195 @@
+// Built-in shader of `InstancingPlugin::sdf`, drawing the shape of each `SdfInstanceData`
+// instance from its signed distance function on a square mesh from -1 to 1.
+
+#import bevy_render::view::View
+#import fundamentals::instance_batch::{InstanceBatch, instance_batch}
+
+@group(0) @binding(0) var<uniform> view: View;
+
+#ifdef STORAGE_INSTANCING
+@group(3) @binding(0) var<storage, read> static_data: array<f32>;
+@group(3) @binding(1) var<storage, read> changing_data: array<f32>;
+#endif
+
+// Shape kinds, mirrored in `sdf.rs`.
+const CIRCLE: u32 = 0u;
+const RING: u32 = 1u;
+const ROUNDED_BOX: u32 = 2u;
+const SEGMENT: u32 = 3u;
+
+struct Vertex {
+#ifdef VERTEX_POSITIONS_3D
+    @location(0) position: vec3f,
+#else
+    @location(0) position: vec2f,
+#endif
+#ifndef STORAGE_INSTANCING
+    @location(4) color: vec4f,
+    @location(5) offset: vec2f,
+    @location(6) shape: u32,
+    @location(7) outline_width: f32,
+    @location(8) params: vec4f,
+    @location(9) outline_color: vec4f,
+    @location(10) scale: vec2f,
+#endif
+};
+
+struct Instance {
+    color: vec4f,
+    offset: vec2f,
+    shape: u32,
+    outline_width: f32,
+    params: vec4f,
+    outline_color: vec4f,
+    scale: vec2f,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+    @location(1) outline_color: vec4f,
+    // Position relative to the center of the shape, before the instance transform.
+    @location(2) shape_position: vec2f,
+    @location(3) @interpolate(flat) params: vec4f,
+    @location(4) @interpolate(flat) shape: u32,
+    @location(5) @interpolate(flat) outline_width: f32,
+};
+
+#ifdef STORAGE_INSTANCING
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    let s = instance_index * #{STATIC_INSTANCE_STRIDE}u;
+    let c = instance_index * #{CHANGING_INSTANCE_STRIDE}u;
+
+    var instance: Instance;
+    instance.color = vec4f(
+        static_data[s], static_data[s + 1u], static_data[s + 2u], static_data[s + 3u]
+    );
+    instance.offset = vec2f(static_data[s + 4u], static_data[s + 5u]);
+    instance.shape = bitcast<u32>(static_data[s + 6u]);
+    instance.outline_width = static_data[s + 7u];
+    instance.params = vec4f(
+        static_data[s + 8u], static_data[s + 9u], static_data[s + 10u], static_data[s + 11u]
+    );
+    instance.outline_color = vec4f(
+        static_data[s + 12u], static_data[s + 13u], static_data[s + 14u], static_data[s + 15u]
+    );
+    instance.scale = vec2f(changing_data[c], changing_data[c + 1u]);
+    return instance;
+}
+#else
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    return Instance(
+        vertex.color,
+        vertex.offset,
+        vertex.shape,
+        vertex.outline_width,
+        vertex.params,
+        vertex.outline_color,
+        vertex.scale,
+    );
+}
+#endif
+
+// Half size of the box around the shape and its outline, mirrors `SdfInstanceData::half_extents`.
+fn half_extents(instance: Instance) -> vec2f {
+    var shape: vec2f;
+    switch instance.shape {
+        case CIRCLE, RING: {
+            shape = vec2f(instance.params.x);
+        }
+        case ROUNDED_BOX: {
+            shape = instance.params.xy;
+        }
+        default: {
+            shape = abs(instance.params.xy) + instance.params.z * 0.5;
+        }
+    }
+    return shape + instance.outline_width;
+}
+
+// Shape units covered by a pixel along a direction in the batch's local space.
+fn pixel_size(batch: InstanceBatch, direction: vec2f) -> f32 {
+    let world = batch.world_from_local * vec4f(direction, 0.0, 0.0);
+    let clip = (view.clip_from_world * world).xy;
+    return 1.0 / max(length(clip * view.viewport.zw * 0.5), 1e-6);
+}
+
+fn signed_distance(shape: u32, params: vec4f, p: vec2f) -> f32 {
+    switch shape {
+        case CIRCLE: {
+            return length(p) - params.x;
+        }
+        case RING: {
+            let half_thickness = params.y * 0.5;
+            return abs(length(p) - params.x + half_thickness) - half_thickness;
+        }
+        case ROUNDED_BOX: {
+            let q = abs(p) - params.xy + params.z;
+            return length(max(q, vec2f(0.0))) + min(max(q.x, q.y), 0.0) - params.z;
+        }
+        default: {
+            // From -params.xy to params.xy.
+            let start = -params.xy;
+            let direction = 2.0 * params.xy;
+            let h = clamp(dot(p - start, direction) / max(dot(direction, direction), 1e-12), 0.0, 1.0);
+            return length(p - start - direction * h) - params.z * 0.5;
+        }
+    }
+}
+
+@vertex
+fn vs(
+    vertex: Vertex,
+    @builtin(instance_index) instance_index: u32
+) -> VertexOutput {
+    let instance = load_instance(vertex, instance_index);
+    let batch = instance_batch(instance_index);
+
+    // A pixel of margin keeps the smoothed edge inside the quad.
+    let margin = vec2f(
+        pixel_size(batch, vec2f(instance.scale.x, 0.0)),
+        pixel_size(batch, vec2f(0.0, instance.scale.y)),
+    );
+    let shape_position = vertex.position.xy * (half_extents(instance) + margin);
+
+    var vertex_output: VertexOutput;
+    let instance_position = shape_position * instance.scale;
+    let local_position = vec4f(instance_position + instance.offset, 0.0, 1.0);
+    let world_position = batch.world_from_local * local_position;
+    vertex_output.position = view.clip_from_world * world_position;
+    vertex_output.color = instance.color * batch.tint;
+    vertex_output.outline_color = instance.outline_color * batch.tint;
+    vertex_output.shape_position = shape_position;
+    vertex_output.params = instance.params;
+    vertex_output.shape = instance.shape;
+    vertex_output.outline_width = instance.outline_width;
+    return vertex_output;
+}
+
+@fragment
+fn fs(vertex_output: VertexOutput) -> @location(0) vec4f {
+    let distance = signed_distance(
+        vertex_output.shape, vertex_output.params, vertex_output.shape_position
+    );
+    // Edges fade over a pixel, centered on the exact edge.
+    let smoothing = max(fwidth(distance), 1e-6);
+    let fill = saturate(0.5 - distance / smoothing);
+    let coverage = saturate(0.5 - (distance - vertex_output.outline_width) / smoothing);
+    if coverage <= 0.0 {
+        discard;
+    }
+
+    var color = mix(vertex_output.outline_color, vertex_output.color, fill / coverage);
+    color.a *= coverage;
+#ifdef ALPHA_MASK
+    if color.a < 0.5 {
+        discard;
+    }
+#endif
+#ifdef BLEND_MULTIPLY
+    // Transparent parts leave the target unchanged.
+    return vec4f(mix(vec3f(1.0), color.rgb, color.a), color.a);
+#else
+    return color;
+#endif
+}
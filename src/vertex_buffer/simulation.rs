@@ -0,0 +1,242 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{Node, NodeRunError, RenderGraphContext, RenderLabel},
+        render_resource::{
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
+            BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePipelineDescriptor,
+            PipelineCache, ShaderStages, ShaderType,
+            binding_types::{storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer},
+            encase::UniformBuffer,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+    },
+    shader::ShaderDefVal,
+};
+
+use super::{
+    InstancePayload,
+    gpu_culling::{ComputeDispatch, WORKGROUP_SIZE, run_dispatches},
+    instance_material::InstanceData,
+};
+
+/// Compute pipeline running the update function given to
+/// [`InstancingPlugin::with_simulation_shader`](super::InstancingPlugin::with_simulation_shader),
+/// only present where it can be used.
+#[derive(Resource)]
+pub(super) struct InstanceSimulationPipeline<S, C> {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S: InstancePayload, C: InstancePayload> InstanceSimulationPipeline<S, C> {
+    /// Returns `None` if the device lacks compute shaders or the payloads can't be read as
+    /// floats.
+    pub(super) fn new(world: &World, shader: Handle<Shader>) -> Option<Self> {
+        let render_device = world.resource::<RenderDevice>();
+        let limits = render_device.limits();
+        if limits.max_compute_workgroup_size_x < WORKGROUP_SIZE
+            || limits.max_storage_buffers_per_shader_stage < 2
+        {
+            warn!(
+                "Compute shaders are not supported on this device, batches of {} and {} are not \
+                simulated",
+                std::any::type_name::<S>(),
+                std::any::type_name::<C>(),
+            );
+            return None;
+        }
+        if !size_of::<S>().is_multiple_of(4) || !size_of::<C>().is_multiple_of(4) {
+            warn!(
+                "Instance simulation requires payload sizes that are multiples of 4 bytes, \
+                batches of {} and {} are not simulated",
+                std::any::type_name::<S>(),
+                std::any::type_name::<C>(),
+            );
+            return None;
+        }
+
+        let layout = render_device.create_bind_group_layout(
+            "instance_simulation_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<SimulationParams>(false),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+
+        let pipeline =
+            world
+                .resource::<PipelineCache>()
+                .queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("instance_simulation_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: vec![],
+                    shader,
+                    shader_defs: vec![
+                        ShaderDefVal::UInt(
+                            "STATIC_INSTANCE_STRIDE".into(),
+                            (size_of::<S>() / 4) as u32,
+                        ),
+                        ShaderDefVal::UInt(
+                            "CHANGING_INSTANCE_STRIDE".into(),
+                            (size_of::<C>() / 4) as u32,
+                        ),
+                    ],
+                    entry_point: Some("update".into()),
+                    zero_initialize_workgroup_memory: true,
+                });
+
+        Some(InstanceSimulationPipeline {
+            layout,
+            pipeline,
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Mirrors `SimulationParams` in `instance_simulation.wgsl`.
+#[derive(ShaderType)]
+struct SimulationParams {
+    time: f32,
+    delta_time: f32,
+    instance_count: u32,
+}
+
+/// Binds the [`InstanceData`] buffers of a simulated batch.
+#[derive(Component)]
+pub(super) struct InstanceSimulationData<S: InstancePayload, C: InstancePayload> {
+    /// Capacity of the [`InstanceData`] buffers this was created for.
+    capacity: usize,
+    params: Buffer,
+    bind_group: BindGroup,
+    marker: PhantomData<fn() -> (S, C)>,
+}
+
+impl<S: InstancePayload, C: InstancePayload> InstanceSimulationData<S, C> {
+    fn new(
+        render_device: &RenderDevice,
+        simulation_pipeline: &InstanceSimulationPipeline<S, C>,
+        instance_data: &InstanceData<S, C>,
+    ) -> Self {
+        let params = render_device.create_buffer(&BufferDescriptor {
+            label: Some("instance simulation params buffer"),
+            size: SimulationParams::min_size().get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = render_device.create_bind_group(
+            "instance_simulation_bind_group",
+            &simulation_pipeline.layout,
+            &BindGroupEntries::sequential((
+                params.as_entire_binding(),
+                instance_data.buffers[0].as_entire_binding(),
+                instance_data.buffers[1].as_entire_binding(),
+            )),
+        );
+
+        InstanceSimulationData {
+            capacity: instance_data.capacity,
+            params,
+            bind_group,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Compute passes to run this frame, shared by every payload type like
+/// [`GpuCullingDispatches`](super::gpu_culling::GpuCullingDispatches).
+#[derive(Resource, Default)]
+pub(super) struct InstanceSimulationDispatches(Vec<ComputeDispatch>);
+
+pub(super) fn clear_instance_simulation_dispatches(
+    mut dispatches: ResMut<InstanceSimulationDispatches>,
+) {
+    dispatches.0.clear();
+}
+
+pub(super) fn prepare_instance_simulation<S: InstancePayload, C: InstancePayload>(
+    mut commands: Commands,
+    query: Query<(
+        Entity,
+        &InstanceData<S, C>,
+        Option<&InstanceSimulationData<S, C>>,
+    )>,
+    simulation_pipeline: Option<Res<InstanceSimulationPipeline<S, C>>>,
+    time: Res<Time>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut dispatches: ResMut<InstanceSimulationDispatches>,
+) {
+    let Some(simulation_pipeline) = simulation_pipeline else {
+        return;
+    };
+
+    for (entity, instance_data, simulation_data) in &query {
+        if instance_data.length == 0 {
+            continue;
+        }
+
+        let new_simulation_data = match simulation_data {
+            Some(simulation_data) if simulation_data.capacity == instance_data.capacity => None,
+            _ => Some(InstanceSimulationData::new(
+                &render_device,
+                &simulation_pipeline,
+                instance_data,
+            )),
+        };
+        let simulation_data = new_simulation_data.as_ref().or(simulation_data).unwrap();
+
+        let mut params = UniformBuffer::new(Vec::new());
+        params
+            .write(&SimulationParams {
+                time: time.elapsed_secs_wrapped(),
+                delta_time: time.delta_secs(),
+                instance_count: instance_data.length as u32,
+            })
+            .unwrap();
+        render_queue.write_buffer(&simulation_data.params, 0, params.as_ref());
+
+        dispatches.0.push(ComputeDispatch {
+            pipeline: simulation_pipeline.pipeline,
+            bind_group: simulation_data.bind_group.clone(),
+            workgroups: (instance_data.length as u32).div_ceil(WORKGROUP_SIZE),
+        });
+
+        if let Some(simulation_data) = new_simulation_data {
+            commands.entity(entity).insert(simulation_data);
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub(super) struct InstanceSimulationLabel;
+
+/// Runs the [`InstanceSimulationDispatches`] before the culling pass, so it sees the updated
+/// instances.
+#[derive(Default)]
+pub(super) struct InstanceSimulationNode;
+
+impl Node for InstanceSimulationNode {
+    fn run<'w>(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        // Batches stay still until the pipeline is compiled.
+        run_dispatches(
+            render_context,
+            world.resource::<PipelineCache>(),
+            &world.resource::<InstanceSimulationDispatches>().0,
+            "instance_simulation",
+        );
+        Ok(())
+    }
+}
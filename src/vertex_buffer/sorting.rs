@@ -0,0 +1,48 @@
+/// Indices of the first `len` instances ordered back to front, i.e. by ascending layer.
+///
+/// Instances without a layer are on layer 0. Instances on the same layer keep their order.
+pub(super) fn back_to_front_order(layers: &[f32], len: usize) -> Vec<u32> {
+    let layer = |index: u32| layers.get(index as usize).copied().unwrap_or(0.0);
+    let mut order: Vec<u32> = (0..len as u32).collect();
+    order.sort_by(|&a, &b| layer(a).total_cmp(&layer(b)));
+    order
+}
+
+/// Copies `data` in the given `order`.
+pub(super) fn gather<T: Copy>(data: &[T], order: &[u32]) -> Vec<T> {
+    order.iter().map(|&index| data[index as usize]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_are_ordered_back_to_front() {
+        let layers = [2.0, -1.0, 0.5, 10.0, -3.0];
+        let order = back_to_front_order(&layers, layers.len());
+        assert_eq!(order, [4, 1, 2, 0, 3]);
+
+        let sorted = gather(&layers, &order);
+        assert!(sorted.is_sorted());
+    }
+
+    #[test]
+    fn equal_layers_keep_their_order() {
+        let layers = [1.0, 0.0, 1.0, 0.0, 1.0];
+        assert_eq!(back_to_front_order(&layers, layers.len()), [1, 3, 0, 2, 4]);
+    }
+
+    #[test]
+    fn missing_layers_are_zero() {
+        let layers = [1.0, -1.0];
+        assert_eq!(back_to_front_order(&layers, 4), [1, 2, 3, 0]);
+        assert_eq!(back_to_front_order(&layers, 1), [0]);
+    }
+
+    #[test]
+    fn gather_reorders_payloads() {
+        let payloads = ['a', 'b', 'c', 'd'];
+        assert_eq!(gather(&payloads, &[3, 0, 2, 1]), ['d', 'a', 'c', 'b']);
+    }
+}
@@ -0,0 +1,78 @@
+use bevy::{math::Affine2, prelude::*};
+
+use super::ChangingInstanceData;
+
+/// Static payload of [`InstancingPlugin::strokes`](super::InstancingPlugin::strokes), filling
+/// the batch's mesh with `color` and its [stroke](crate::Shape2d::with_stroke) with
+/// `stroke_color`.
+#[derive(
+    Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, fundamentals_macros::InstanceAttributes,
+)]
+#[repr(C)]
+pub struct StrokedInstanceData {
+    pub color: [f32; 4],
+    pub offset: Vec2,
+    pub stroke_color: [f32; 4],
+    /// Width of the stroke around the mesh, in mesh units before the instance transform.
+    pub stroke_width: f32,
+}
+
+impl Default for StrokedInstanceData {
+    /// White without a stroke.
+    fn default() -> Self {
+        Self {
+            color: [1.0; 4],
+            offset: Vec2::ZERO,
+            stroke_color: [0.0, 0.0, 0.0, 1.0],
+            stroke_width: 0.0,
+        }
+    }
+}
+
+/// Places the mesh like the built-in payloads do.
+pub(super) fn stroked_instance_transform(
+    static_data: &StrokedInstanceData,
+    changing_data: &ChangingInstanceData,
+) -> Affine2 {
+    Affine2::from_translation(static_data.offset) * Affine2::from_scale(changing_data.scale)
+}
+
+/// Lets culling account for the stroke of each instance.
+pub(super) fn stroke_width(
+    static_data: &StrokedInstanceData,
+    _changing_data: &ChangingInstanceData,
+) -> f32 {
+    static_data.stroke_width
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::mesh::VertexFormat;
+
+    use super::*;
+    use crate::{InstanceAttributes, StaticInstanceData};
+
+    #[test]
+    fn stroked_layout_keeps_the_builtin_offset() {
+        let layout = StrokedInstanceData::vertex_buffer_layout(4);
+        assert_eq!(layout.array_stride, 11 * 4);
+        assert_eq!(
+            layout
+                .attributes
+                .iter()
+                .map(|attribute| (attribute.shader_location, attribute.format))
+                .collect::<Vec<_>>(),
+            vec![
+                (4, VertexFormat::Float32x4),
+                (5, VertexFormat::Float32x2),
+                (6, VertexFormat::Float32x4),
+                (7, VertexFormat::Float32),
+            ]
+        );
+        // The built-in culling shader reads the offset at the same place.
+        assert_eq!(
+            layout.attributes[1].offset,
+            StaticInstanceData::vertex_buffer_layout(4).attributes[1].offset
+        );
+    }
+}
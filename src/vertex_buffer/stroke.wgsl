@@ -0,0 +1,122 @@
+// Built-in shader of `InstancingPlugin::strokes`, filling the mesh and its stroke with the colors
+// of each `StrokedInstanceData` instance.
+
+#import bevy_render::view::View
+#import fundamentals::instance_batch::instance_batch
+
+@group(0) @binding(0) var<uniform> view: View;
+
+#ifdef STORAGE_INSTANCING
+@group(3) @binding(0) var<storage, read> static_data: array<f32>;
+@group(3) @binding(1) var<storage, read> changing_data: array<f32>;
+#endif
+
+struct Vertex {
+#ifdef VERTEX_POSITIONS_3D
+    @location(0) position: vec3f,
+#else
+    @location(0) position: vec2f,
+#endif
+#ifdef VERTEX_COLORS
+    @location(3) vertex_color: vec4f,
+#endif
+#ifndef STORAGE_INSTANCING
+    @location(4) color: vec4f,
+    @location(5) offset: vec2f,
+    @location(6) stroke_color: vec4f,
+    @location(7) stroke_width: f32,
+    @location(8) scale: vec2f,
+#endif
+#ifdef VERTEX_STROKES
+    @location(#{STROKE_OFFSET_LOCATION}) stroke_offset: vec3f,
+#endif
+};
+
+struct Instance {
+    color: vec4f,
+    offset: vec2f,
+    stroke_color: vec4f,
+    stroke_width: f32,
+    scale: vec2f,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) color: vec4f,
+};
+
+#ifdef STORAGE_INSTANCING
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    let s = instance_index * #{STATIC_INSTANCE_STRIDE}u;
+    let c = instance_index * #{CHANGING_INSTANCE_STRIDE}u;
+
+    var instance: Instance;
+    instance.color = vec4f(
+        static_data[s], static_data[s + 1u], static_data[s + 2u], static_data[s + 3u]
+    );
+    instance.offset = vec2f(static_data[s + 4u], static_data[s + 5u]);
+    instance.stroke_color = vec4f(
+        static_data[s + 6u], static_data[s + 7u], static_data[s + 8u], static_data[s + 9u]
+    );
+    instance.stroke_width = static_data[s + 10u];
+    instance.scale = vec2f(changing_data[c], changing_data[c + 1u]);
+    return instance;
+}
+#else
+fn load_instance(vertex: Vertex, instance_index: u32) -> Instance {
+    return Instance(
+        vertex.color,
+        vertex.offset,
+        vertex.stroke_color,
+        vertex.stroke_width,
+        vertex.scale,
+    );
+}
+#endif
+
+@vertex
+fn vs(
+    vertex: Vertex,
+    @builtin(instance_index) instance_index: u32
+) -> VertexOutput {
+    let instance = load_instance(vertex, instance_index);
+    let batch = instance_batch(instance_index);
+
+    var vertex_output: VertexOutput;
+    vertex_output.color = instance.color * batch.tint;
+#ifdef VERTEX_STROKES
+    // Stroke vertices move out of the mesh by the stroke width.
+    let mesh_position = vertex.position.xy + vertex.stroke_offset.xy * instance.stroke_width;
+    if vertex.stroke_offset.z > 0.5 {
+        vertex_output.color = instance.stroke_color * batch.tint;
+    }
+#else
+    let mesh_position = vertex.position.xy;
+#endif
+#ifdef VERTEX_COLORS
+    vertex_output.color *= vertex.vertex_color;
+#endif
+
+    // Instance offsets are in the entity's local space.
+    let instance_position = mesh_position * instance.scale;
+    let local_position = vec4f(instance_position + instance.offset, 0.0, 1.0);
+    let world_position = batch.world_from_local * local_position;
+    vertex_output.position = view.clip_from_world * world_position;
+    return vertex_output;
+}
+
+@fragment
+fn fs(vertex_output: VertexOutput) -> @location(0) vec4f {
+    let color = vertex_output.color;
+#ifdef ALPHA_MASK
+    if color.a < 0.5 {
+        discard;
+    }
+#endif
+#ifdef BLEND_MULTIPLY
+    // Transparent parts leave the target unchanged.
+    return vec4f(mix(vec3f(1.0), color.rgb, color.a), color.a);
+#else
+    return color;
+#endif
+}
@@ -0,0 +1,246 @@
+use std::any::TypeId;
+
+use bevy::{math::Affine2, prelude::*};
+
+/// A 2D affine transform packed the way the instance vertex layout expects it.
+///
+/// A vertex `p` of the mesh is transformed to `x_axis * p.x + y_axis * p.y + translation`.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    bytemuck::Pod,
+    bytemuck::Zeroable,
+    fundamentals_macros::InstanceAttributes,
+)]
+#[repr(C)]
+pub struct PackedAffine2 {
+    pub x_axis: Vec2,
+    pub y_axis: Vec2,
+    pub translation: Vec2,
+}
+
+impl PackedAffine2 {
+    pub const IDENTITY: Self = Self {
+        x_axis: Vec2::X,
+        y_axis: Vec2::Y,
+        translation: Vec2::ZERO,
+    };
+
+    pub fn from_scale(scale: Vec2) -> Self {
+        Self {
+            x_axis: Vec2::new(scale.x, 0.0),
+            y_axis: Vec2::new(0.0, scale.y),
+            translation: Vec2::ZERO,
+        }
+    }
+
+    pub fn from_rotation(rotation: Rot2) -> Self {
+        Transform2d::from_rotation(rotation).into()
+    }
+
+    pub fn from_translation(translation: Vec2) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn transform_point(&self, point: Vec2) -> Vec2 {
+        self.x_axis * point.x + self.y_axis * point.y + self.translation
+    }
+}
+
+impl Default for PackedAffine2 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl From<Affine2> for PackedAffine2 {
+    fn from(affine: Affine2) -> Self {
+        Self {
+            x_axis: affine.matrix2.x_axis,
+            y_axis: affine.matrix2.y_axis,
+            translation: affine.translation,
+        }
+    }
+}
+
+impl From<PackedAffine2> for Affine2 {
+    fn from(packed: PackedAffine2) -> Self {
+        Affine2::from_cols(packed.x_axis, packed.y_axis, packed.translation)
+    }
+}
+
+impl From<Transform2d> for PackedAffine2 {
+    fn from(transform: Transform2d) -> Self {
+        Self {
+            x_axis: transform.rotation * Vec2::new(transform.scale.x, 0.0),
+            y_axis: transform.rotation * Vec2::new(0.0, transform.scale.y),
+            translation: transform.translation,
+        }
+    }
+}
+
+/// Per-instance data that is expected to be updated often, like
+/// [`ChangingInstanceData`](super::ChangingInstanceData) but with a full 2D affine transform
+/// instead of a scale.
+///
+/// Takes 24 bytes per instance instead of 8, see
+/// [`InstancingPlugin::affine`](super::InstancingPlugin::affine).
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    bytemuck::Pod,
+    bytemuck::Zeroable,
+    fundamentals_macros::InstanceAttributes,
+)]
+#[repr(C)]
+pub struct AffineInstanceData {
+    /// Rotation, scale and shear of the instance, applied to the mesh before `offset`.
+    pub transform: PackedAffine2,
+}
+
+/// Whether shaders are compiled with `AFFINE_INSTANCES` to read `C` as an [`AffineInstanceData`].
+pub(super) fn is_affine<C: 'static>() -> bool {
+    TypeId::of::<C>() == TypeId::of::<AffineInstanceData>()
+}
+
+/// Translation, rotation and non-uniform scale of a single instance.
+///
+/// Scale is applied first, then rotation, then translation. Convert it into a [`PackedAffine2`]
+/// to store it in an [`AffineInstanceData`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform2d {
+    pub translation: Vec2,
+    pub rotation: Rot2,
+    pub scale: Vec2,
+}
+
+impl Transform2d {
+    pub const IDENTITY: Self = Self {
+        translation: Vec2::ZERO,
+        rotation: Rot2::IDENTITY,
+        scale: Vec2::ONE,
+    };
+
+    pub fn from_translation(translation: Vec2) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_rotation(rotation: Rot2) -> Self {
+        Self {
+            rotation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_scale(scale: Vec2) -> Self {
+        Self {
+            scale,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn with_translation(mut self, translation: Vec2) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Rot2) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec2) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn to_packed(self) -> PackedAffine2 {
+        self.into()
+    }
+}
+
+impl Default for Transform2d {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Projects a 3D [`Transform`] onto the XY plane, keeping only the rotation around Z.
+impl From<Transform> for Transform2d {
+    fn from(transform: Transform) -> Self {
+        let (angle, _, _) = transform.rotation.to_euler(EulerRot::ZYX);
+        Self {
+            translation: transform.translation.truncate(),
+            rotation: Rot2::radians(angle),
+            scale: transform.scale.truncate(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    #[test]
+    fn packed_transform_matches_affine() {
+        let transform = Transform2d::from_translation(Vec2::new(3.0, -1.0))
+            .with_rotation(Rot2::radians(0.7))
+            .with_scale(Vec2::new(2.0, 0.5));
+        let packed = transform.to_packed();
+        let affine = Affine2::from_scale_angle_translation(
+            transform.scale,
+            transform.rotation.as_radians(),
+            transform.translation,
+        );
+
+        for point in [Vec2::ZERO, Vec2::X, Vec2::Y, Vec2::new(-1.5, 4.0)] {
+            assert!(
+                packed
+                    .transform_point(point)
+                    .abs_diff_eq(affine.transform_point2(point), 1e-5)
+            );
+        }
+        assert_eq!(
+            PackedAffine2::from(affine),
+            PackedAffine2::from(Affine2::from(packed))
+        );
+    }
+
+    #[test]
+    fn rotation_is_counter_clockwise() {
+        let packed = PackedAffine2::from_rotation(Rot2::radians(FRAC_PI_2));
+        assert!(packed.transform_point(Vec2::X).abs_diff_eq(Vec2::Y, 1e-6));
+        assert!(packed.transform_point(Vec2::Y).abs_diff_eq(-Vec2::X, 1e-6));
+    }
+
+    #[test]
+    fn from_3d_transform_keeps_z_rotation() {
+        let transform = Transform::from_xyz(1.0, 2.0, 5.0)
+            .with_rotation(Quat::from_rotation_z(0.3))
+            .with_scale(Vec3::new(2.0, 3.0, 1.0));
+        let transform_2d = Transform2d::from(transform);
+        assert_eq!(transform_2d.translation, Vec2::new(1.0, 2.0));
+        assert!((transform_2d.rotation.as_radians() - 0.3).abs() < 1e-6);
+        assert_eq!(transform_2d.scale, Vec2::new(2.0, 3.0));
+    }
+
+    #[test]
+    fn from_3d_transform_ignores_tilt() {
+        let rotation = Quat::from_rotation_z(0.3) * Quat::from_rotation_x(0.5);
+        let transform_2d = Transform2d::from(Transform::from_rotation(rotation));
+        assert!((transform_2d.rotation.as_radians() - 0.3).abs() < 1e-6);
+    }
+}